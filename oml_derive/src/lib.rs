@@ -0,0 +1,258 @@
+mod oml_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Expr, Fields, Lit, LitStr};
+
+/// Derive a typed loader and a template generator, see `oml::OmlConfig`
+///
+/// Field attributes:
+/// - `#[oml(default)]` or `#[oml(default = expr)]` when the key is absent
+/// - `#[oml(rename = "key")]` to read another key
+/// - `#[oml(path = "a.b")]` to read a nested path relative to the struct
+#[proc_macro_derive(OmlConfig, attributes(oml))]
+pub fn derive_oml_config(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Build an `OmlExpr` from inline oml source, syntax errors are reported at compile time
+///
+/// ```ignore
+/// let expr = oml::oml! {
+///     [hello]
+///     value = 12
+///     name = $"hello {value + 12}"
+/// };
+/// ```
+#[proc_macro]
+pub fn oml(input: TokenStream) -> TokenStream {
+    let content = oml_macro::tokens_to_source(input.into());
+    let src = quote! { #content };
+    match oml_macro::expand_source(&content, src, proc_macro2::Span::call_site()) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Like `oml!`, reading the file relative to the crate root
+#[proc_macro]
+pub fn include_oml(input: TokenStream) -> TokenStream {
+    let file = parse_macro_input!(input as LitStr);
+    let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let path = std::path::Path::new(&root).join(file.value());
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) => {
+            let msg = format!("couldn't read {}: {}", path.display(), err);
+            return syn::Error::new(file.span(), msg).to_compile_error().into();
+        }
+    };
+    // include_str! lets cargo rebuild when the file changes
+    let path = path.to_string_lossy();
+    let src = quote! { ::core::include_str!(#path) };
+    match oml_macro::expand_source(&content, src, file.span()) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+enum FieldDefault {
+    None,
+    Trait,
+    Expr(Expr),
+}
+
+struct FieldAttrs {
+    key: String,
+    default: FieldDefault,
+}
+
+fn parse_docs(attrs: &[Attribute]) -> Vec<String> {
+    let mut docs = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("doc")) {
+        if let syn::Meta::NameValue(meta) = &attr.meta {
+            if let Expr::Lit(syn::ExprLit {
+                lit: Lit::Str(doc), ..
+            }) = &meta.value
+            {
+                docs.push(doc.value());
+            }
+        }
+    }
+    docs
+}
+
+fn parse_field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut ret = FieldAttrs {
+        key: field.ident.as_ref().unwrap().to_string(),
+        default: FieldDefault::None,
+    };
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("oml"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                ret.default = match meta.input.peek(syn::Token![=]) {
+                    true => FieldDefault::Expr(meta.value()?.parse()?),
+                    false => FieldDefault::Trait,
+                };
+            } else if meta.path.is_ident("rename") {
+                let key = meta.value()?.parse::<LitStr>()?;
+                if key.value().contains('.') {
+                    let msg = "rename takes a single key, use `path` for nested keys";
+                    return Err(syn::Error::new(key.span(), msg));
+                }
+                ret.key = key.value();
+            } else if meta.path.is_ident("path") {
+                ret.key = meta.value()?.parse::<LitStr>()?.value();
+            } else {
+                return Err(meta.error("unsupported oml attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(ret)
+}
+
+/// Literal defaults are written into the template as they are, other fields use a placeholder
+fn template_value(default: &FieldDefault, ty: &syn::Type) -> syn::Result<TokenStream2> {
+    if let FieldDefault::Expr(Expr::Lit(syn::ExprLit { lit, .. })) = default {
+        let text = match lit {
+            // oml string literals are read verbatim, nothing can be escaped
+            Lit::Str(s) => {
+                let value = s.value();
+                if !oml_syntax::is_literal_text(&value, true) || value.contains(['\n', '\r']) {
+                    let msg = "default can't be written as an oml string literal";
+                    return Err(syn::Error::new(s.span(), msg));
+                }
+                Some(format!("\"{}\"", value))
+            }
+            Lit::Int(n) => Some(n.base10_digits().to_string()),
+            Lit::Float(f) => Some(f.base10_digits().to_string()),
+            Lit::Bool(b) => Some(b.value.to_string()),
+            _ => None,
+        };
+        if let Some(text) = text {
+            return Ok(quote! { #text.to_string() });
+        }
+    }
+    Ok(quote! { <#ty as ::oml::FromOmlValue>::oml_placeholder() })
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "OmlConfig requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "OmlConfig requires a struct with named fields",
+            ))
+        }
+    };
+    let struct_docs = parse_docs(&input.attrs);
+    let mut loads = vec![];
+    let mut entries = vec![];
+    for field in fields.iter() {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let attrs = parse_field_attrs(field)?;
+        let key = &attrs.key;
+        let docs = parse_docs(&field.attrs);
+        let default = match &attrs.default {
+            FieldDefault::None => None,
+            FieldDefault::Trait => Some(quote! { ::core::default::Default::default() }),
+            FieldDefault::Expr(Expr::Lit(syn::ExprLit {
+                lit: Lit::Str(s), ..
+            })) => Some(quote! { ::core::convert::Into::into(#s) }),
+            FieldDefault::Expr(expr) => Some(quote! { #expr }),
+        };
+        loads.push(match default {
+            Some(default) => quote! {
+                #ident: match value.get(#key) {
+                    Some(value) if !value.is_none() => {
+                        <#ty as ::oml::FromOmlValue>::from_oml_value(value, &join(#key))?
+                    }
+                    _ => #default,
+                }
+            },
+            None => quote! {
+                #ident: match value.get(#key) {
+                    Some(value) => <#ty as ::oml::FromOmlValue>::from_oml_value(value, &join(#key))?,
+                    None => <#ty as ::oml::FromOmlValue>::from_missing(&join(#key))?,
+                }
+            },
+        });
+        let value = template_value(&attrs.default, ty)?;
+        entries.push(quote! {
+            let docs: &[&str] = &[#(#docs),*];
+            if !<#ty as ::oml::FromOmlValue>::oml_template_entries(&join(#key), docs, &mut nested) {
+                let docs = docs.iter().map(|doc| doc.to_string()).collect();
+                entries.push(::oml::OmlTemplateEntry::Field((#key.to_string(), docs, #value)));
+            }
+        });
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::oml::FromOmlValue for #name #ty_generics #where_clause {
+            fn from_oml_value(
+                value: &::oml::OmlValue,
+                path: &str,
+            ) -> ::core::result::Result<Self, ::oml::OmlError> {
+                let join = |key: &str| match path.is_empty() {
+                    true => key.to_string(),
+                    false => format!("{}.{}", path, key),
+                };
+                if !value.is_map() {
+                    let msg = format!("expected map, found {}", value.as_str());
+                    return Err(::oml::OmlError::Deserialize((path.to_string(), msg)));
+                }
+                Ok(Self {
+                    #(#loads,)*
+                })
+            }
+
+            fn from_missing(path: &str) -> ::core::result::Result<Self, ::oml::OmlError> {
+                // a missing group is fine as long as every field has a default
+                let value = ::oml::OmlValue::Map(::oml::IndexMap::new());
+                <Self as ::oml::FromOmlValue>::from_oml_value(&value, path)
+            }
+
+            fn oml_template_entries(
+                path: &str,
+                docs: &[&str],
+                entries: &mut Vec<::oml::OmlTemplateEntry>,
+            ) -> bool {
+                let join = |key: &str| match path.is_empty() {
+                    true => key.to_string(),
+                    false => format!("{}.{}", path, key),
+                };
+                let struct_docs: &[&str] = &[#(#struct_docs),*];
+                let docs = if docs.is_empty() { struct_docs } else { docs };
+                let docs = docs.iter().map(|doc| doc.to_string()).collect();
+                entries.push(::oml::OmlTemplateEntry::Group((path.to_string(), docs)));
+                let mut nested = vec![];
+                #(#entries)*
+                entries.extend(nested);
+                true
+            }
+        }
+
+        impl #impl_generics ::oml::OmlConfig for #name #ty_generics #where_clause {}
+    })
+}
@@ -0,0 +1,57 @@
+use proc_macro2::{Delimiter, Spacing, Span, TokenStream, TokenTree};
+use quote::quote;
+
+/// Check the source with the parser `OmlExpr::from_str` uses and expand to code parsing it at runtime
+pub fn expand_source(content: &str, src: TokenStream, span: Span) -> syn::Result<TokenStream> {
+    if let Err(err) = oml_syntax::parse_oml(content) {
+        let msg = format!("invalid oml source:\n{}", err);
+        return Err(syn::Error::new(span, msg));
+    }
+    Ok(quote! {
+        ::oml::OmlExpr::from_str(#src).expect("oml source was checked at compile time")
+    })
+}
+
+/// Rebuild oml source from rust tokens, only inserting whitespace where tokens would merge
+pub fn tokens_to_source(tokens: TokenStream) -> String {
+    let mut ret = "".to_string();
+    write_tokens(&mut ret, tokens);
+    ret
+}
+
+fn write_tokens(ret: &mut String, tokens: TokenStream) {
+    // (is word, is joint punct, is punct)
+    let mut last = (false, false, false);
+    for token in tokens.into_iter() {
+        match token {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                ret.push_str(open);
+                write_tokens(ret, group.stream());
+                ret.push_str(close);
+                last = (false, false, false);
+            }
+            TokenTree::Punct(punct) => {
+                // keep `a - -1` apart, while `$"..."`, `@let` and `=>` stay joined
+                if last.2 && !last.1 {
+                    ret.push(' ');
+                }
+                ret.push(punct.as_char());
+                let sticky = matches!(punct.as_char(), '$' | '@');
+                last = (false, punct.spacing() == Spacing::Joint || sticky, !sticky);
+            }
+            TokenTree::Ident(_) | TokenTree::Literal(_) => {
+                if last.0 {
+                    ret.push(' ');
+                }
+                ret.push_str(&token.to_string());
+                last = (true, false, false);
+            }
+        }
+    }
+}
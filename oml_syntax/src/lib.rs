@@ -0,0 +1,72 @@
+use pest::iterators::Pair;
+use pest::Parser;
+use pest_derive::Parser;
+use std::collections::HashMap;
+
+#[derive(Parser)]
+#[grammar = "../oml.pest"]
+pub struct OmlParser;
+
+/// Parse a whole document and check what the grammar can't, such as template instances
+///
+/// Used by `OmlExpr::from_str` and by the `oml!` macros, so both report the same errors.
+pub fn parse_oml(content: &str) -> Result<Pair<'_, Rule>, String> {
+    let root = match OmlParser::parse(Rule::oml, content) {
+        Ok(mut root) => root.next().unwrap(),
+        Err(err) => return Err(err.to_string()),
+    };
+    check_templates(root.clone())?;
+    Ok(root)
+}
+
+/// Whether `s` can be written between the quotes of a string literal, which is read verbatim
+///
+/// A quote can only be written as part of `\"`, and `closing` is set when the text is followed
+/// by the closing quote, which a trailing backslash would swallow.
+pub fn is_literal_text(s: &str, closing: bool) -> bool {
+    let bare_quote = (s.char_indices()).any(|(pos, c)| c == '"' && !s[..pos].ends_with('\\'));
+    !(bare_quote || (closing && s.ends_with('\\')))
+}
+
+/// Every instance names a defined template and passes exactly its parameters
+fn check_templates(root: Pair<'_, Rule>) -> Result<(), String> {
+    // template name => (parameter, has default)
+    let mut templates = HashMap::new();
+    for root_item in root.clone().into_inner() {
+        if root_item.as_rule() == Rule::template_block {
+            let mut root_items = root_item.into_inner();
+            let name = root_items.next().unwrap().as_str();
+            let params: Vec<_> = (root_items.next().unwrap().into_inner())
+                .map(|param| {
+                    let mut param = param.into_inner();
+                    (param.next().unwrap().as_str(), param.next().is_some())
+                })
+                .collect();
+            templates.insert(name, params);
+        }
+    }
+    let insts = (root.into_inner())
+        .filter(|root_item| root_item.as_rule() == Rule::group_block)
+        .flat_map(|root_item| root_item.into_inner())
+        .filter(|root_item| root_item.as_rule() == Rule::template_inst);
+    for inst in insts {
+        let mut root_items = inst.into_inner().skip(1);
+        let name = root_items.next().unwrap().as_str();
+        let params =
+            (templates.get(name)).ok_or_else(|| format!("undefined template: {}", name))?;
+        let args: Vec<_> = (root_items)
+            .map(|arg| arg.into_inner().next().unwrap().as_str())
+            .collect();
+        if let Some(arg) = args
+            .iter()
+            .find(|arg| !params.iter().any(|(p, _)| p == *arg))
+        {
+            return Err(format!("unknown argument {} for template {}.", arg, name));
+        }
+        if let Some((param, _)) = (params.iter()).find(|(p, default)| !default && !args.contains(p))
+        {
+            return Err(format!("missing argument {} for template {}.", param, name));
+        }
+    }
+    Ok(())
+}
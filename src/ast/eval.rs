@@ -1,10 +1,13 @@
+use super::oml_env::env;
+use super::oml_error::OmlError;
 use super::oml_value::{ApplyExt, OmlValue};
 
 pub(crate) struct Op1Evaluator {}
 
 impl Op1Evaluator {
-    pub fn eval_prefix(op: &str, right: OmlValue) -> Result<OmlValue, String> {
+    pub fn eval_prefix(op: &str, right: OmlValue) -> Result<OmlValue, OmlError> {
         Ok(match (op, right) {
+            (_, OmlValue::None) => return Err(OmlError::NoneOperand(op.to_string())),
             ("++", OmlValue::Int64(n)) => OmlValue::Int64(n + 1),
             ("++", OmlValue::Float64(n)) => OmlValue::Float64(n + 1.0),
             ("--", OmlValue::Int64(n)) => OmlValue::Int64(n - 1),
@@ -13,17 +16,18 @@ impl Op1Evaluator {
             ("-", OmlValue::Int64(n)) => OmlValue::Int64(-n),
             ("-", OmlValue::Float64(n)) => OmlValue::Float64(-n),
             ("~", OmlValue::Int64(n)) => OmlValue::Int64(!n),
-            _ => return Err(format!("illegal operator: {}", op)),
+            _ => return Err(OmlError::IllegalOperator(op.to_string())),
         })
     }
 
-    pub fn eval_suffix(op: &str, left: OmlValue) -> Result<OmlValue, String> {
+    pub fn eval_suffix(op: &str, left: OmlValue) -> Result<OmlValue, OmlError> {
         Ok(match (op, left) {
+            (_, OmlValue::None) => return Err(OmlError::NoneOperand(op.to_string())),
             ("++", OmlValue::Int64(n)) => OmlValue::Int64(n + 1),
             ("++", OmlValue::Float64(n)) => OmlValue::Float64(n + 1.0),
             ("--", OmlValue::Int64(n)) => OmlValue::Int64(n - 1),
             ("--", OmlValue::Float64(n)) => OmlValue::Float64(n - 1.0),
-            _ => return Err(format!("illegal operator: {}", op)),
+            _ => return Err(OmlError::IllegalOperator(op.to_string())),
         })
     }
 }
@@ -31,8 +35,17 @@ impl Op1Evaluator {
 pub(crate) struct Op2Evaluator {}
 
 impl Op2Evaluator {
-    pub fn eval(left: OmlValue, op: &str, right: OmlValue) -> Result<OmlValue, String> {
+    pub fn eval(left: OmlValue, op: &str, right: OmlValue) -> Result<OmlValue, OmlError> {
         match (left, op, right) {
+            (left, "in", right) => Ok(OmlValue::Bool(Self::eval_in(left, right)?)),
+            (left, "not in", right) => Ok(OmlValue::Bool(!Self::eval_in(left, right)?)),
+            (OmlValue::None, "==", right) => Ok(OmlValue::Bool(right.is_none())),
+            (left, "==", OmlValue::None) => Ok(OmlValue::Bool(left.is_none())),
+            (OmlValue::None, "!=", right) => Ok(OmlValue::Bool(!right.is_none())),
+            (left, "!=", OmlValue::None) => Ok(OmlValue::Bool(!left.is_none())),
+            (OmlValue::None, _, _) | (_, _, OmlValue::None) => {
+                Err(OmlError::NoneOperand(op.to_string()))
+            }
             (OmlValue::Bool(left), _, OmlValue::Bool(right)) => {
                 Ok(OmlValue::Bool(Self::eval_bool(left, op, right)?))
             }
@@ -62,45 +75,70 @@ impl Op2Evaluator {
                 left.apply(right.clone());
                 Ok(OmlValue::Map(left))
             }
-            _ => Err(format!("illegal operator: {}", op)),
+            _ => Err(OmlError::IllegalOperator(op.to_string())),
+        }
+    }
+
+    fn eval_in(left: OmlValue, right: OmlValue) -> Result<bool, OmlError> {
+        match (left, right) {
+            (left, OmlValue::Array(right)) => Ok(right.into_iter().any(|item| {
+                let ret = Self::eval(left.clone(), "==", item);
+                matches!(ret, Ok(OmlValue::Bool(true)))
+            })),
+            (OmlValue::String(left), OmlValue::Map(right)) => Ok(right.contains_key(&left)),
+            (OmlValue::String(left), OmlValue::String(right)) => Ok(right.contains(&left)),
+            (OmlValue::None, _) | (_, OmlValue::None) => {
+                Err(OmlError::NoneOperand("in".to_string()))
+            }
+            _ => Err(OmlError::IllegalOperator("in".to_string())),
         }
     }
 
-    fn eval_bool(left: bool, op: &str, right: bool) -> Result<bool, String> {
+    fn eval_bool(left: bool, op: &str, right: bool) -> Result<bool, OmlError> {
         Ok(match op {
             "&&" => left && right,
             "||" => left || right,
             "==" => left == right,
             "!=" => left != right,
-            _ => return Err(format!("illegal operator: {}", op)),
+            _ => return Err(OmlError::IllegalOperator(op.to_string())),
         })
     }
 
-    fn eval_int64(left: i64, op: &str, right: i64) -> Result<OmlValue, String> {
-        Ok(OmlValue::Int64(match op {
-            "+" => left + right,
-            "-" => left - right,
-            "*" => left * right,
-            "/" => left / right,
+    fn eval_int64(left: i64, op: &str, right: i64) -> Result<OmlValue, OmlError> {
+        let ret = match op {
+            "+" => left.checked_add(right),
+            "-" => left.checked_sub(right),
+            "*" => left.checked_mul(right),
+            "/" | "%" if right == 0 => return Err(OmlError::Eval("division by zero".to_string())),
+            "/" => left.checked_div(right),
             "**" if right < 0 => return Ok(OmlValue::Float64((left as f64).powf(right as f64))),
-            "**" => left.pow(right as u32),
-            "%" => left % right,
-            "|" => left | right,
-            "&" => left & right,
-            "<<" => left << right,
-            ">>" => left >> right,
-            "^" => left ^ right,
+            "**" => u32::try_from(right)
+                .ok()
+                .and_then(|right| left.checked_pow(right)),
+            "%" => left.checked_rem(right),
+            "|" => Some(left | right),
+            "&" => Some(left & right),
+            "<<" => u32::try_from(right)
+                .ok()
+                .and_then(|right| left.checked_shl(right)),
+            ">>" => u32::try_from(right)
+                .ok()
+                .and_then(|right| left.checked_shr(right)),
+            "^" => Some(left ^ right),
             "<" => return Ok(OmlValue::Bool(left < right)),
             "<=" => return Ok(OmlValue::Bool(left <= right)),
             ">" => return Ok(OmlValue::Bool(left > right)),
             ">=" => return Ok(OmlValue::Bool(left >= right)),
             "==" => return Ok(OmlValue::Bool(left == right)),
             "!=" => return Ok(OmlValue::Bool(left != right)),
-            _ => return Err(format!("illegal operator: {}", op)),
-        }))
+            _ => return Err(OmlError::IllegalOperator(op.to_string())),
+        };
+        let msg = || format!("integer overflow: {} {} {}", left, op, right);
+        ret.map(OmlValue::Int64)
+            .ok_or_else(|| OmlError::Eval(msg()))
     }
 
-    fn eval_float64(left: f64, op: &str, right: f64) -> Result<OmlValue, String> {
+    fn eval_float64(left: f64, op: &str, right: f64) -> Result<OmlValue, OmlError> {
         Ok(OmlValue::Float64(match op {
             "+" => left + right,
             "-" => left - right,
@@ -114,16 +152,47 @@ impl Op2Evaluator {
             ">=" => return Ok(OmlValue::Bool(left >= right)),
             "==" => return Ok(OmlValue::Bool(left == right)),
             "!=" => return Ok(OmlValue::Bool(left != right)),
-            _ => return Err(format!("illegal operator: {}", op)),
+            _ => return Err(OmlError::IllegalOperator(op.to_string())),
         }))
     }
 
-    fn eval_string(left: &str, op: &str, right: &str) -> Result<OmlValue, String> {
+    fn eval_string(left: &str, op: &str, right: &str) -> Result<OmlValue, OmlError> {
         match op {
             "+" => Ok(OmlValue::String(format!("{}{}", left, right))),
             "==" => Ok(OmlValue::Bool(left == right)),
             "!=" => Ok(OmlValue::Bool(left != right)),
-            _ => Err(format!("illegal operator: {}", op)),
+            _ => Err(OmlError::IllegalOperator(op.to_string())),
+        }
+    }
+}
+
+pub(crate) struct FuncEvaluator {}
+
+impl FuncEvaluator {
+    pub fn invoke(name: &str, args: Vec<OmlValue>) -> Result<OmlValue, OmlError> {
+        match (name, &args[..]) {
+            ("range", [OmlValue::Int64(end)]) => Ok(Self::range(0, *end, 1)),
+            ("range", [OmlValue::Int64(start), OmlValue::Int64(end)]) => {
+                Ok(Self::range(*start, *end, 1))
+            }
+            ("range", [OmlValue::Int64(start), OmlValue::Int64(end), OmlValue::Int64(step)])
+                if *step != 0 =>
+            {
+                Ok(Self::range(*start, *end, *step))
+            }
+            ("env", [OmlValue::String(name)]) => env(name, None),
+            ("env", [OmlValue::String(name), default]) => env(name, Some(default)),
+            _ => Err(OmlError::IllegalFunction(name.to_string())),
+        }
+    }
+
+    fn range(start: i64, end: i64, step: i64) -> OmlValue {
+        let mut ret = vec![];
+        let mut n = start;
+        while (step > 0 && n < end) || (step < 0 && n > end) {
+            ret.push(OmlValue::Int64(n));
+            n += step;
         }
+        OmlValue::Array(ret)
     }
 }
@@ -0,0 +1,182 @@
+use super::oml_error::OmlError;
+use super::oml_value::OmlValue;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormatAlign {
+    Left,
+    Right,
+    Center,
+}
+
+/// Format spec after `:` inside interpolation braces, `[[fill]align][+][#][0][width][.precision][type]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSpec {
+    pub fill: char,
+    pub align: Option<FormatAlign>,
+    pub sign: bool,
+    pub alternate: bool,
+    pub zero: bool,
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+    pub ty: Option<char>,
+}
+
+impl FormatSpec {
+    /// Parse a spec already validated by the grammar, with or without the leading `:`
+    pub fn parse(spec: &str) -> Self {
+        let spec: Vec<_> = spec.strip_prefix(':').unwrap_or(spec).chars().collect();
+        let to_align = |c: char| match c {
+            '<' => Some(FormatAlign::Left),
+            '>' => Some(FormatAlign::Right),
+            '^' => Some(FormatAlign::Center),
+            _ => None,
+        };
+        let mut ret = FormatSpec {
+            fill: ' ',
+            align: None,
+            sign: false,
+            alternate: false,
+            zero: false,
+            width: None,
+            precision: None,
+            ty: None,
+        };
+        let mut idx = 0;
+        if let Some(align) = spec.get(1).and_then(|c| to_align(*c)) {
+            ret.fill = spec[0];
+            ret.align = Some(align);
+            idx = 2;
+        } else if let Some(align) = spec.first().and_then(|c| to_align(*c)) {
+            ret.align = Some(align);
+            idx = 1;
+        }
+        let take_flag = |idx: &mut usize, flag: char| {
+            let ret = spec.get(*idx) == Some(&flag);
+            if ret {
+                *idx += 1;
+            }
+            ret
+        };
+        ret.sign = take_flag(&mut idx, '+');
+        ret.alternate = take_flag(&mut idx, '#');
+        ret.zero = take_flag(&mut idx, '0');
+        let take_num = |idx: &mut usize| {
+            let start = *idx;
+            while spec.get(*idx).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+                *idx += 1;
+            }
+            spec[start..*idx].iter().collect::<String>().parse().ok()
+        };
+        ret.width = take_num(&mut idx);
+        if spec.get(idx) == Some(&'.') {
+            idx += 1;
+            ret.precision = take_num(&mut idx);
+        }
+        ret.ty = spec.get(idx).copied();
+        ret
+    }
+
+    pub fn format(&self, value: &OmlValue) -> Result<String, OmlError> {
+        let illegal = || OmlError::FormatSpec(self.to_string());
+        let (negative, prefix, body) = match value {
+            OmlValue::Int64(n) => {
+                if self.precision.is_some() {
+                    return Err(illegal());
+                }
+                let n_abs = n.unsigned_abs();
+                let (prefix, body) = match self.ty {
+                    None => ("", n_abs.to_string()),
+                    Some('x') => ("0x", format!("{:x}", n_abs)),
+                    Some('X') => ("0x", format!("{:X}", n_abs)),
+                    Some('b') => ("0b", format!("{:b}", n_abs)),
+                    Some('o') => ("0o", format!("{:o}", n_abs)),
+                    Some(_) => return Err(illegal()),
+                };
+                (*n < 0, if self.alternate { prefix } else { "" }, body)
+            }
+            OmlValue::Float64(f) if self.ty.is_none() && !self.alternate => {
+                let body = match self.precision {
+                    Some(precision) => format!("{:.*}", precision, f.abs()),
+                    None => f.abs().to_string(),
+                };
+                (f.is_sign_negative(), "", body)
+            }
+            _ if self.ty.is_some() || self.alternate || self.sign || self.zero => {
+                return Err(illegal());
+            }
+            _ => {
+                let mut body = value.as_str();
+                if let Some(precision) = self.precision {
+                    body = body.chars().take(precision).collect();
+                }
+                let fill_len = self.fill_len(body.chars().count());
+                let align = self.align.unwrap_or(FormatAlign::Left);
+                return Ok(self.pad(body, fill_len, align));
+            }
+        };
+        let sign = match (negative, self.sign) {
+            (true, _) => "-",
+            (false, true) => "+",
+            (false, false) => "",
+        };
+        let fill_len = self.fill_len(sign.len() + prefix.len() + body.chars().count());
+        Ok(match (self.zero, self.align) {
+            (true, None) => format!("{}{}{}{}", sign, prefix, "0".repeat(fill_len), body),
+            (_, align) => {
+                let body = format!("{}{}{}", sign, prefix, body);
+                self.pad(body, fill_len, align.unwrap_or(FormatAlign::Right))
+            }
+        })
+    }
+
+    fn fill_len(&self, len: usize) -> usize {
+        self.width.unwrap_or(0).saturating_sub(len)
+    }
+
+    fn pad(&self, body: String, fill_len: usize, align: FormatAlign) -> String {
+        let (left, right) = match align {
+            FormatAlign::Left => (0, fill_len),
+            FormatAlign::Right => (fill_len, 0),
+            FormatAlign::Center => (fill_len / 2, fill_len - fill_len / 2),
+        };
+        let fill = self.fill.to_string();
+        format!("{}{}{}", fill.repeat(left), body, fill.repeat(right))
+    }
+}
+
+impl Display for FormatSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, ":")?;
+        if let Some(align) = self.align {
+            if self.fill != ' ' {
+                write!(f, "{}", self.fill)?;
+            }
+            let align = match align {
+                FormatAlign::Left => '<',
+                FormatAlign::Right => '>',
+                FormatAlign::Center => '^',
+            };
+            write!(f, "{}", align)?;
+        }
+        if self.sign {
+            write!(f, "+")?;
+        }
+        if self.alternate {
+            write!(f, "#")?;
+        }
+        if self.zero {
+            write!(f, "0")?;
+        }
+        if let Some(width) = self.width {
+            write!(f, "{}", width)?;
+        }
+        if let Some(precision) = self.precision {
+            write!(f, ".{}", precision)?;
+        }
+        if let Some(ty) = self.ty {
+            write!(f, "{}", ty)?;
+        }
+        Ok(())
+    }
+}
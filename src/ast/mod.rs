@@ -1,3 +1,18 @@
 pub mod eval;
+pub mod format_spec;
+pub mod oml_cst;
+pub mod oml_env;
+pub mod oml_error;
 pub mod oml_expr;
+pub mod oml_format;
+pub mod oml_func;
+pub mod oml_printer;
 pub mod oml_value;
+
+/// Path of `key` below `path`, keys and array indices alike
+pub(crate) fn join_path(path: &str, key: impl std::fmt::Display) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
@@ -0,0 +1,381 @@
+use super::oml_error::OmlError;
+use super::oml_expr::{OmlExpr, OmlParser, Rule};
+use super::oml_printer::write_expr;
+use pest::Parser;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OmlCstKind {
+    Document,
+    /// `[group]`, `[[group]]` or template instance with its pairs
+    Block,
+    Template,
+    Head,
+    /// `key = value`
+    Pair,
+    /// `@let`, `@fn` and `@assert`
+    Local,
+    /// `@if cond`
+    Annotation,
+    Key,
+    Value,
+    /// Punctuation and keywords between the other nodes
+    Token,
+    /// Whitespace and comments
+    Trivia,
+}
+
+/// Node of the concrete syntax tree, the children of a node cover its whole span
+#[derive(Debug, Clone)]
+pub struct OmlCstNode {
+    kind: OmlCstKind,
+    range: Range<usize>,
+    children: Vec<OmlCstNode>,
+}
+
+impl OmlCstNode {
+    pub fn kind(&self) -> OmlCstKind {
+        self.kind
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    pub fn children(&self) -> &[OmlCstNode] {
+        &self.children
+    }
+
+    fn child(&self, kind: OmlCstKind) -> Option<&OmlCstNode> {
+        self.children.iter().find(|node| node.kind == kind)
+    }
+
+    fn children_of(&self, kind: OmlCstKind) -> impl Iterator<Item = &OmlCstNode> {
+        self.children.iter().filter(move |node| node.kind == kind)
+    }
+
+    /// Pest spans may run over trailing whitespace and comments, stop at the last token instead
+    fn token_end(pair: &pest::iterators::Pair<'_, Rule>, source: &str) -> usize {
+        let span = pair.as_span();
+        let inner_end = match pair.clone().into_inner().last() {
+            Some(inner) => Self::token_end(&inner, source),
+            None => return span.end(),
+        };
+        // text after the last inner pair holds no literals, only tokens and trivia
+        let mut gap = vec![];
+        Self::lex_gap(source, inner_end..span.end(), &mut gap);
+        (gap.iter().rev())
+            .find(|node| node.kind == OmlCstKind::Token)
+            .map(|node| node.range.end)
+            .unwrap_or(inner_end)
+    }
+
+    fn build(pair: pest::iterators::Pair<'_, Rule>, kind: OmlCstKind, source: &str) -> Self {
+        let range = pair.as_span().start()..Self::token_end(&pair, source);
+        let mut nodes = vec![];
+        // keys and values are edited as a whole, their inner structure isn't kept
+        if !matches!(kind, OmlCstKind::Key | OmlCstKind::Value) {
+            Self::collect(pair.into_inner(), source, &mut nodes);
+        }
+        Self::with_gaps(kind, range, nodes, source)
+    }
+
+    fn collect(pairs: pest::iterators::Pairs<'_, Rule>, source: &str, nodes: &mut Vec<OmlCstNode>) {
+        for pair in pairs {
+            let kind = match pair.as_rule() {
+                Rule::group_block => OmlCstKind::Block,
+                Rule::template_block => OmlCstKind::Template,
+                Rule::group_head | Rule::group_array_head | Rule::template_inst => OmlCstKind::Head,
+                Rule::assign_pair => OmlCstKind::Pair,
+                Rule::let_pair | Rule::fn_pair | Rule::assert_pair => OmlCstKind::Local,
+                Rule::anno_if => OmlCstKind::Annotation,
+                Rule::ids | Rule::id => OmlCstKind::Key,
+                Rule::expr => OmlCstKind::Value,
+                Rule::EOI => continue,
+                _ => {
+                    Self::collect(pair.into_inner(), source, nodes);
+                    continue;
+                }
+            };
+            nodes.push(Self::build(pair, kind, source));
+        }
+    }
+
+    /// Fill the text between child nodes with token and trivia leaves
+    fn with_gaps(
+        kind: OmlCstKind,
+        range: Range<usize>,
+        nodes: Vec<OmlCstNode>,
+        source: &str,
+    ) -> Self {
+        let mut children = vec![];
+        let mut pos = range.start;
+        for node in nodes {
+            Self::lex_gap(source, pos..node.range.start, &mut children);
+            pos = node.range.end;
+            children.push(node);
+        }
+        if !children.is_empty() || !matches!(kind, OmlCstKind::Key | OmlCstKind::Value) {
+            Self::lex_gap(source, pos..range.end, &mut children);
+        }
+        OmlCstNode {
+            kind,
+            range,
+            children,
+        }
+    }
+
+    fn lex_gap(source: &str, range: Range<usize>, nodes: &mut Vec<OmlCstNode>) {
+        let is_trivia_start = |s: &str| {
+            s.starts_with(char::is_whitespace) || s.starts_with("//") || s.starts_with("/*")
+        };
+        let mut pos = range.start;
+        while pos < range.end {
+            let rest = &source[pos..range.end];
+            let (len, kind) = if rest.starts_with("//") {
+                (rest.find('\n').unwrap_or(rest.len()), OmlCstKind::Trivia)
+            } else if rest.starts_with("/*") {
+                let len = rest.find("*/").map(|n| n + 2).unwrap_or(rest.len());
+                (len, OmlCstKind::Trivia)
+            } else if rest.starts_with(char::is_whitespace) {
+                let len = rest
+                    .find(|c: char| !c.is_whitespace())
+                    .unwrap_or(rest.len());
+                (len, OmlCstKind::Trivia)
+            } else {
+                let mut len = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+                while len < rest.len() && !is_trivia_start(&rest[len..]) {
+                    len += rest[len..]
+                        .chars()
+                        .next()
+                        .map(|c| c.len_utf8())
+                        .unwrap_or(1);
+                }
+                (len, OmlCstKind::Token)
+            };
+            nodes.push(OmlCstNode {
+                kind,
+                range: pos..pos + len,
+                children: vec![],
+            });
+            pos += len;
+        }
+    }
+}
+
+/// An `.oml` source with its syntax tree, edits keep comments and formatting untouched
+#[derive(Debug, Clone)]
+pub struct OmlDocument {
+    source: String,
+    root: OmlCstNode,
+}
+
+/// Assignment found for a path, or the place to insert a missing one
+enum PairLookup {
+    Found(Range<usize>),
+    /// (insert offset, indentation, key relative to the block)
+    Block((usize, String, String)),
+    /// An ancestor of the path is assigned a value, e.g. an inline map
+    Shadowed(String),
+    Missing,
+}
+
+impl OmlDocument {
+    pub fn parse(source: &str) -> Result<Self, OmlError> {
+        let root = match OmlParser::parse(Rule::oml, source) {
+            Ok(mut root) => root.next().unwrap(),
+            Err(err) => return Err(OmlError::Parse(err.to_string())),
+        };
+        let mut nodes = vec![];
+        OmlCstNode::collect(root.into_inner(), source, &mut nodes);
+        Ok(OmlDocument {
+            source: source.to_string(),
+            root: OmlCstNode::with_gaps(OmlCstKind::Document, 0..source.len(), nodes, source),
+        })
+    }
+
+    pub fn root(&self) -> &OmlCstNode {
+        &self.root
+    }
+
+    pub fn text(&self, node: &OmlCstNode) -> &str {
+        &self.source[node.range.clone()]
+    }
+
+    pub(crate) fn text_at(&self, range: Range<usize>) -> &str {
+        &self.source[range]
+    }
+
+    pub fn to_expr(&self) -> Result<OmlExpr, OmlError> {
+        OmlExpr::from_str(&self.source)
+    }
+
+    /// Source text of the value assigned at `path`, e.g. `image.tag` or `routes.1.path`
+    pub fn value_at(&self, path: &str) -> Option<&str> {
+        match self.lookup(path) {
+            PairLookup::Found(range) => Some(&self.source[range]),
+            _ => None,
+        }
+    }
+
+    /// Span of the value at `path`, or of the head when `path` names a group
+    pub fn range_at(&self, path: &str) -> Option<Range<usize>> {
+        if let PairLookup::Found(range) = self.lookup(path) {
+            return Some(range);
+        }
+        let blocks = self.blocks();
+        let (_, block) = blocks.into_iter().find(|(head, _)| head == path)?;
+        block.child(OmlCstKind::Head).map(|head| head.range())
+    }
+
+    /// 1-based line and column of a byte offset
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let before = &self.source[..offset.min(self.source.len())];
+        let line_start = before.rfind('\n').map(|n| n + 1).unwrap_or(0);
+        let line = before.matches('\n').count() + 1;
+        (line, before[line_start..].chars().count() + 1)
+    }
+
+    /// Replace the value at `path`, only that span is rewritten
+    ///
+    /// A missing key is added to the last group it belongs to, or to a new group at the end.
+    pub fn set_value_at(&mut self, path: &str, value: &OmlExpr) -> Result<(), OmlError> {
+        let value = write_expr(value)?;
+        let mut source = self.source.clone();
+        match self.lookup(path) {
+            PairLookup::Found(range) => source.replace_range(range, &value),
+            PairLookup::Block((pos, indent, key)) => {
+                source.insert_str(pos, &format!("\n{}{} = {}", indent, key, value))
+            }
+            PairLookup::Shadowed(key) => {
+                let msg = format!("{}: {} is already assigned a value", path, key);
+                return Err(OmlError::Serialize(msg));
+            }
+            PairLookup::Missing => {
+                let (group, key) = match path.rsplit_once('.') {
+                    Some((group, key)) if Self::is_ids(group) && Self::is_ids(key) => (group, key),
+                    _ => {
+                        let msg = format!("{}: no group to hold the value", path);
+                        return Err(OmlError::Serialize(msg));
+                    }
+                };
+                if !source.is_empty() && !source.ends_with('\n') {
+                    source.push('\n');
+                }
+                if !source.is_empty() {
+                    source.push('\n');
+                }
+                source.push_str(&format!("[{}]\n{} = {}\n", group, key, value));
+            }
+        }
+        *self = Self::parse(&source)?;
+        Ok(())
+    }
+
+    fn is_ids(s: &str) -> bool {
+        s.split('.').all(|id| {
+            let mut chars = id.chars();
+            matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+    }
+
+    /// Unconditional blocks with the path of the group they write to
+    fn blocks(&self) -> Vec<(String, &OmlCstNode)> {
+        let mut ret = vec![];
+        let mut array_counts: Vec<(String, usize)> = vec![];
+        for block in self.root.children_of(OmlCstKind::Block) {
+            if block.child(OmlCstKind::Annotation).is_some() {
+                continue;
+            }
+            let head = match block.child(OmlCstKind::Head) {
+                Some(head) => head,
+                None => continue,
+            };
+            let mut path = match head.child(OmlCstKind::Key) {
+                Some(key) => self.text(key).to_string(),
+                None => continue,
+            };
+            if self.text(head).starts_with("[[") {
+                let index = match array_counts.iter_mut().find(|(name, _)| *name == path) {
+                    Some((_, count)) => {
+                        *count += 1;
+                        *count - 1
+                    }
+                    None => {
+                        array_counts.push((path.clone(), 1));
+                        0
+                    }
+                };
+                path = format!("{}.{}", path, index);
+            }
+            ret.push((path, block));
+        }
+        ret
+    }
+
+    fn lookup(&self, path: &str) -> PairLookup {
+        let blocks = self.blocks();
+        // the first assignment of a key is the one that takes effect
+        for (head, block) in blocks.iter() {
+            for pair in block.children_of(OmlCstKind::Pair) {
+                if pair.child(OmlCstKind::Annotation).is_some() {
+                    continue;
+                }
+                if let (Some(key), Some(value)) =
+                    (pair.child(OmlCstKind::Key), pair.child(OmlCstKind::Value))
+                {
+                    let key = format!("{}.{}", head, self.text(key));
+                    if key == path {
+                        return PairLookup::Found(value.range());
+                    }
+                    if path.starts_with(&key) && path[key.len()..].starts_with('.') {
+                        return PairLookup::Shadowed(key);
+                    }
+                }
+            }
+        }
+        let mut best: Option<(&String, &OmlCstNode)> = None;
+        for (head, block) in blocks.iter() {
+            let is_parent = path.starts_with(&format!("{}.", head));
+            if is_parent
+                && best
+                    .map(|(best, _)| head.len() >= best.len())
+                    .unwrap_or(true)
+            {
+                best = Some((head, block));
+            }
+        }
+        let (head, block) = match best {
+            Some(best) => best,
+            None => return PairLookup::Missing,
+        };
+        let key = path[head.len() + 1..].to_string();
+        if !Self::is_ids(&key) {
+            return PairLookup::Missing;
+        }
+        let anchor = (block.children.iter())
+            .rev()
+            .find(|node| !matches!(node.kind, OmlCstKind::Token | OmlCstKind::Trivia))
+            .unwrap_or(block);
+        // after the anchor's line, so a trailing comment stays where it is
+        let line_start = self.source[..anchor.range.start]
+            .rfind('\n')
+            .map(|n| n + 1)
+            .unwrap_or(0);
+        let line = &self.source[line_start..];
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        let pos = self.source[anchor.range.end..]
+            .find('\n')
+            .map(|n| anchor.range.end + n)
+            .unwrap_or(self.source.len());
+        PairLookup::Block((pos, indent.to_string(), key))
+    }
+}
+
+impl Display for OmlDocument {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
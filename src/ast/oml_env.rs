@@ -0,0 +1,136 @@
+use super::oml_error::OmlError;
+use super::oml_expr::OmlExpr;
+use super::oml_value::OmlValue;
+use indexmap::IndexMap;
+use std::cell::RefCell;
+
+thread_local! {
+    static ENV_OPTIONS: RefCell<Vec<EnvOptions>> = const { RefCell::new(vec![]) };
+}
+
+/// Environment variables visible to `env()` and `${NAME}` expansion
+///
+/// Nothing is visible unless allowed, so evaluating an untrusted file can't read secrets.
+#[derive(Debug, Clone, Default)]
+pub struct EnvOptions {
+    /// Visible variable names, a trailing `*` allows every name with that prefix
+    pub allow: Vec<String>,
+    /// Fail on undefined variables that have no default instead of expanding to `""`
+    pub strict: bool,
+    /// Variables to read instead of the process environment
+    pub vars: Option<IndexMap<String, String>>,
+}
+
+impl EnvOptions {
+    fn is_allowed(&self, name: &str) -> bool {
+        self.allow
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => pattern == name,
+            })
+    }
+
+    /// Value of a visible variable, unset and empty ones are undefined
+    fn lookup(&self, name: &str) -> Option<String> {
+        if !self.is_allowed(name) {
+            return None;
+        }
+        let value = match &self.vars {
+            Some(vars) => vars.get(name).cloned(),
+            None => std::env::var(name).ok(),
+        };
+        value.filter(|value| !value.is_empty())
+    }
+}
+
+/// Makes the options visible to `env()` while an expression is being evaluated
+pub(crate) struct EnvGuard {}
+
+impl EnvGuard {
+    pub fn install(options: &EnvOptions) -> Self {
+        ENV_OPTIONS.with(|options1| options1.borrow_mut().push(options.clone()));
+        Self {}
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        ENV_OPTIONS.with(|options| options.borrow_mut().pop());
+    }
+}
+
+/// `env(name)` and `env(name, default)`
+pub(crate) fn env(name: &str, default: Option<&OmlValue>) -> Result<OmlValue, OmlError> {
+    let (value, strict) = ENV_OPTIONS.with(|options| match options.borrow().last() {
+        Some(options) => (options.lookup(name), options.strict),
+        None => (None, false),
+    });
+    match (value, default) {
+        (Some(value), _) => Ok(OmlValue::String(value)),
+        (None, Some(default)) => Ok(default.clone()),
+        (None, None) if strict => Err(OmlError::UndefinedEnv(name.to_string())),
+        (None, None) => Ok(OmlValue::String("".to_string())),
+    }
+}
+
+/// What a string literal holding `${NAME}` evaluates as, the literal itself unless `EnvOptions`
+/// are installed
+pub(crate) fn env_string_expr(s: &str) -> OmlExpr {
+    match ENV_OPTIONS.with(|options| options.borrow().is_empty()) {
+        true => OmlExpr::Value(OmlValue::String(s.to_string())),
+        false => expand_env_refs(s.to_string()),
+    }
+}
+
+/// Turn `${NAME}` and `${NAME:-default}` into `env()` calls, `$${` stands for a literal `${`
+fn expand_env_refs(s: String) -> OmlExpr {
+    if !s.contains("${") {
+        return OmlExpr::Value(OmlValue::String(s));
+    }
+    let mut strs = vec!["".to_string()];
+    let mut exprs = vec![];
+    let mut rest = &s[..];
+    while let Some(pos) = rest.find("${") {
+        let (text, tail) = rest.split_at(pos);
+        let last = strs.last_mut().unwrap();
+        if let Some(text) = text.strip_suffix('$') {
+            last.push_str(text);
+            last.push_str("${");
+            rest = &tail[2..];
+            continue;
+        }
+        last.push_str(text);
+        let Some(end) = tail.find('}') else {
+            last.push_str(tail);
+            rest = "";
+            break;
+        };
+        let (name, default) = match tail[2..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&tail[2..end], None),
+        };
+        let is_name =
+            !name.is_empty() && (name.chars()).all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_name {
+            last.push_str(&tail[..end + 1]);
+            rest = &tail[end + 1..];
+            continue;
+        }
+        let mut args = vec![OmlExpr::Value(OmlValue::String(name.to_string()))];
+        if let Some(default) = default {
+            args.push(OmlExpr::Value(OmlValue::String(default.to_string())));
+        }
+        exprs.push((
+            OmlExpr::InvokeFunc((Box::new(OmlExpr::None), "env".to_string(), args)),
+            None,
+        ));
+        strs.push("".to_string());
+        rest = &tail[end + 1..];
+    }
+    strs.last_mut().unwrap().push_str(rest);
+    match exprs.is_empty() {
+        true => OmlExpr::Value(OmlValue::String(strs.concat())),
+        false => OmlExpr::FormatString((strs, exprs)),
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OmlError {
+    Parse(String),
+    IllegalOperator(String),
+    NoneOperand(String),
+    IllegalFunction(String),
+    CallDepth(String),
+    FormatSpec(String),
+    NonExhaustive(String),
+    UndefinedEnv(String),
+    /// Every failed `@assert`, as `(group path, source offset of the assert, message)`
+    Assert(Vec<(String, usize, String)>),
+    /// Path of the failing field and the reason
+    Deserialize((String, String)),
+    Serialize(String),
+    Io(String),
+    Eval(String),
+    /// An evaluation error and the path of the value being evaluated
+    At((String, Box<OmlError>)),
+}
+
+impl OmlError {
+    /// Attach the path of the value being evaluated, the innermost path is kept
+    pub(crate) fn at(self, path: &str) -> Self {
+        match self {
+            err @ (OmlError::At(_) | OmlError::Assert(_)) => err,
+            err => OmlError::At((path.to_string(), Box::new(err))),
+        }
+    }
+}
+
+impl Display for OmlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OmlError::Parse(err) => write!(f, "{}", err),
+            OmlError::IllegalOperator(op) => write!(f, "illegal operator: {}", op),
+            OmlError::NoneOperand(op) => write!(f, "none operand for operator: {}", op),
+            OmlError::IllegalFunction(name) => write!(f, "illegal function: {}", name),
+            OmlError::CallDepth(name) => write!(f, "call depth exceeded in function: {}", name),
+            OmlError::FormatSpec(spec) => write!(f, "illegal format spec: {}", spec),
+            OmlError::NonExhaustive(val) => write!(f, "non-exhaustive match for value: {}", val),
+            OmlError::UndefinedEnv(name) => write!(f, "undefined environment variable: {}", name),
+            OmlError::Assert(failures) => {
+                write!(f, "assertion failed:")?;
+                for (path, _, message) in failures.iter() {
+                    let path = if path.is_empty() { "root" } else { path };
+                    write!(f, "\n  {}: {}", path, message)?;
+                }
+                Ok(())
+            }
+            OmlError::Deserialize((path, err)) => {
+                let path = if path.is_empty() { "root" } else { path };
+                write!(f, "{}: {}", path, err)
+            }
+            OmlError::Serialize(err) => write!(f, "{}", err),
+            OmlError::Io(err) => write!(f, "{}", err),
+            OmlError::Eval(err) => write!(f, "{}", err),
+            OmlError::At((path, err)) => {
+                let path = if path.is_empty() { "root" } else { path };
+                write!(f, "{}: {}", path, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OmlError {}
+
+impl From<OmlError> for String {
+    fn from(err: OmlError) -> Self {
+        err.to_string()
+    }
+}
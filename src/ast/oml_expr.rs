@@ -1,17 +1,20 @@
 use super::eval::{Op1Evaluator, Op2Evaluator};
+use super::format_spec::FormatSpec;
+use super::oml_env::{env_string_expr, EnvGuard, EnvOptions};
+use super::oml_error::OmlError;
+use super::oml_func::FuncTableGuard;
 use super::oml_value::OmlValue;
 use crate::string_utils::IntoBaseExt;
+use indexmap::IndexMap;
 use pest::Parser;
-use pest_derive::Parser;
 use std::cell::UnsafeCell;
-use std::collections::HashMap;
 use std::ops::{Index, IndexMut};
 use std::sync::OnceLock;
 
 static NULL_EXPR: OmlExpr = OmlExpr::None;
 
-fn get_op2_level(op: &str) -> usize {
-    static OP2_LEVELS: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+pub(super) fn get_op2_level(op: &str) -> usize {
+    static OP2_LEVELS: OnceLock<IndexMap<&'static str, usize>> = OnceLock::new();
     *OP2_LEVELS
         .get_or_init(|| {
             [
@@ -30,6 +33,8 @@ fn get_op2_level(op: &str) -> usize {
                 ("<=", 5),
                 (">", 5),
                 (">=", 5),
+                ("in", 5),
+                ("not in", 5),
                 ("==", 6),
                 ("!=", 6),
                 ("&&", 7),
@@ -42,25 +47,33 @@ fn get_op2_level(op: &str) -> usize {
         .unwrap_or(&9)
 }
 
-#[derive(Parser)]
-#[grammar = "../oml.pest"]
-pub struct OmlParser;
+pub use oml_syntax::{OmlParser, Rule};
 
 #[derive(Debug, Clone)]
 pub enum OmlExpr {
     None,
     Value(OmlValue),
+    /// String literal holding `${NAME}` references, expanded only by `evalute_with_env`
+    EnvString(String),
     Array(Vec<OmlExpr>),
-    Map(HashMap<String, OmlExpr>),
+    Map(IndexMap<String, OmlExpr>),
     TempName(String),
     Op1Prefix((String, Box<OmlExpr>)),
     Op1Suffix((Box<OmlExpr>, String)),
     Op2((Box<OmlExpr>, String, Box<OmlExpr>)),
     Op3((Box<OmlExpr>, Box<OmlExpr>, Box<OmlExpr>)),
-    FormatString((Vec<String>, Vec<OmlExpr>)),
+    FormatString((Vec<String>, Vec<(OmlExpr, Option<FormatSpec>)>)),
     AccessVar((Box<OmlExpr>, String)),
     InvokeFunc((Box<OmlExpr>, String, Vec<OmlExpr>)),
     IfAnno(OmlExprIfAnno),
+    Comprehension(OmlExprComprehension),
+    Spread(Box<OmlExpr>),
+    SpreadMap(Vec<OmlExpr>),
+    Let((String, Box<OmlExpr>, Box<OmlExpr>)),
+    LetAnno(Box<OmlExpr>),
+    AssertAnno((Box<OmlExpr>, Box<OmlExpr>)),
+    Lambda((Vec<String>, Box<OmlExpr>)),
+    Match(OmlExprMatch),
 }
 
 #[derive(Debug, Clone)]
@@ -69,6 +82,29 @@ pub struct OmlExprIfAnno {
     pub default: Option<Box<OmlExpr>>,
 }
 
+#[derive(Debug, Clone)]
+pub struct OmlExprMatch {
+    pub value: Box<OmlExpr>,
+    pub arms: Vec<(Vec<OmlMatchPattern>, OmlExpr)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum OmlMatchPattern {
+    Value(OmlValue),
+    /// `start..end` or `start..=end`, either bound may be left open
+    Range((Option<OmlValue>, Option<OmlValue>, bool)),
+    Wildcard,
+}
+
+#[derive(Debug, Clone)]
+pub struct OmlExprComprehension {
+    pub key: Option<Box<OmlExpr>>,
+    pub value: Box<OmlExpr>,
+    pub names: Vec<String>,
+    pub source: Box<OmlExpr>,
+    pub cond: Option<Box<OmlExpr>>,
+}
+
 impl OmlExpr {
     pub fn new() -> Self {
         OmlExpr::None
@@ -81,14 +117,23 @@ impl OmlExpr {
         })
     }
 
-    pub fn from_str(content: &str) -> Result<OmlExpr, String> {
-        match OmlParser::parse(Rule::oml, content) {
-            Ok(mut root) => Self::parse_oml(root.next().unwrap()),
-            Err(err) => Err(err.to_string()),
+    pub fn from_str(content: &str) -> Result<OmlExpr, OmlError> {
+        let root = oml_syntax::parse_oml(content).map_err(OmlError::Parse)?;
+        Self::parse_oml(root)
+    }
+
+    /// Parse a single expression such as `1 + 2` or `$"{name}-1"`
+    pub fn from_expr_str(content: &str) -> Result<OmlExpr, OmlError> {
+        match OmlParser::parse(Rule::single_expr, content) {
+            Ok(mut root) => Ok(Self::parse_expr(
+                root.next().unwrap().into_inner().next().unwrap(),
+            )),
+            Err(err) => Err(OmlError::Parse(err.to_string())),
         }
     }
 
-    fn apply(&mut self, val: OmlExpr) {
+    /// Merge another tree into this one, keys already present here take precedence
+    pub fn apply(&mut self, val: OmlExpr) {
         match self {
             OmlExpr::None => *self = val,
             OmlExpr::Array(arr) => {
@@ -120,26 +165,42 @@ impl OmlExpr {
         }
     }
 
-    fn parse_oml(root: pest::iterators::Pair<'_, Rule>) -> Result<OmlExpr, String> {
+    fn parse_oml(root: pest::iterators::Pair<'_, Rule>) -> Result<OmlExpr, OmlError> {
         let mut ret = Self::new();
+        let mut templates = IndexMap::new();
+        for root_item in root.clone().into_inner() {
+            if root_item.as_rule() == Rule::template_block {
+                let (name, template) = OmlTemplate::parse(root_item);
+                templates.insert(name, template);
+            }
+        }
         for root_item in root.into_inner() {
             match root_item.as_rule() {
                 Rule::group_block => {
-                    let val = Self::parse_block(root_item)?;
+                    let val = Self::parse_block(root_item, &templates)?;
                     ret.apply(val);
                 }
-                Rule::EOI => (),
+                Rule::assert_pair => {
+                    let mut asserts = IndexMap::new();
+                    Self::parse_group_pair(&mut asserts, root_item);
+                    ret.apply(OmlExpr::Map(asserts));
+                }
+                Rule::template_block | Rule::EOI => (),
                 _ => unreachable!(),
             }
         }
         Ok(ret)
     }
 
-    fn parse_block(root: pest::iterators::Pair<'_, Rule>) -> Result<OmlExpr, String> {
+    fn parse_block(
+        root: pest::iterators::Pair<'_, Rule>,
+        templates: &IndexMap<String, OmlTemplate>,
+    ) -> Result<OmlExpr, OmlError> {
         let mut anno_if_expr = None;
         let mut head = "".to_string();
         let mut is_array_head = false;
-        let mut ret = HashMap::new();
+        let mut template_inst = None;
+        let mut ret = IndexMap::new();
         for root_item in root.into_inner() {
             match root_item.as_rule() {
                 Rule::anno_if => {
@@ -150,25 +211,29 @@ impl OmlExpr {
                     head = Self::parse_ids(root_item);
                     is_array_head = true;
                 }
-                Rule::assign_pair => {
-                    let (key, mut value) = Self::parse_assign_pair(root_item);
-                    let mut keys: Vec<_> = key.split('.').map(|key| key.to_string()).collect();
-                    while keys.len() > 1 {
-                        let mut tmp_map = HashMap::new();
-                        tmp_map
-                            .entry(keys.remove(keys.len() - 1))
-                            .or_insert(OmlExpr::None)
-                            .apply(value);
-                        value = OmlExpr::Map(tmp_map);
-                    }
-                    ret.entry(keys.remove(0))
-                        .or_insert(OmlExpr::None)
-                        .apply(value);
+                Rule::template_inst => {
+                    let mut root_items = root_item.into_inner();
+                    head = Self::parse_ids(root_items.next().unwrap());
+                    template_inst = Some(root_items);
                 }
-                _ => unreachable!(),
+                _ => Self::parse_group_pair(&mut ret, root_item),
             }
         }
         let mut ret = OmlExpr::Map(ret);
+        if let Some(mut root_items) = template_inst {
+            let name = root_items.next().unwrap().as_str();
+            let template = templates
+                .get(name)
+                .ok_or_else(|| OmlError::Parse(format!("undefined template: {}", name)))?;
+            let mut args = IndexMap::new();
+            for root_item in root_items {
+                let mut root_items = root_item.into_inner();
+                let key = root_items.next().unwrap().as_str().to_string();
+                args.insert(key, Self::parse_expr(root_items.next().unwrap()));
+            }
+            // values written in the group body override the template
+            ret.apply(template.expand(name, &head, args)?);
+        }
         if is_array_head {
             ret = OmlExpr::Array(vec![ret]);
         }
@@ -186,6 +251,62 @@ impl OmlExpr {
         Ok(ret)
     }
 
+    fn parse_group_pair(
+        map: &mut IndexMap<String, OmlExpr>,
+        root: pest::iterators::Pair<'_, Rule>,
+    ) {
+        match root.as_rule() {
+            Rule::assign_pair => {
+                let (key, mut value) = Self::parse_assign_pair(root);
+                let mut keys: Vec<_> = key.split('.').map(|key| key.to_string()).collect();
+                while keys.len() > 1 {
+                    let mut tmp_map = IndexMap::new();
+                    tmp_map
+                        .entry(keys.remove(keys.len() - 1))
+                        .or_insert(OmlExpr::None)
+                        .apply(value);
+                    value = OmlExpr::Map(tmp_map);
+                }
+                map.entry(keys.remove(0))
+                    .or_insert(OmlExpr::None)
+                    .apply(value);
+            }
+            Rule::fn_pair => {
+                let mut root_items = root.into_inner();
+                let key = root_items.next().unwrap().as_str().to_string();
+                let args = Self::parse_lambda_args(root_items.next().unwrap());
+                let body = Box::new(Self::parse_expr(root_items.next().unwrap()));
+                map.entry(key)
+                    .or_insert(OmlExpr::None)
+                    .apply(OmlExpr::LetAnno(Box::new(OmlExpr::Lambda((args, body)))));
+            }
+            Rule::assert_pair => {
+                // the offset keeps keys unique when blocks of the same group are merged
+                let key = format!("@assert{}", root.as_span().start());
+                let mut root_items = root.into_inner();
+                let cond = root_items.next().unwrap();
+                let message = match root_items.next() {
+                    Some(message) => Self::parse_expr(message),
+                    None => OmlExpr::Value(OmlValue::String(cond.as_str().trim().to_string())),
+                };
+                let cond = Self::parse_expr(cond);
+                map.insert(
+                    key,
+                    OmlExpr::AssertAnno((Box::new(cond), Box::new(message))),
+                );
+            }
+            Rule::let_pair => {
+                let mut root_items = root.into_inner();
+                let key = root_items.next().unwrap().as_str().to_string();
+                let value = Self::parse_expr(root_items.next().unwrap());
+                map.entry(key)
+                    .or_insert(OmlExpr::None)
+                    .apply(OmlExpr::LetAnno(Box::new(value)));
+            }
+            _ => unreachable!(),
+        }
+    }
+
     fn parse_assign_pair(root: pest::iterators::Pair<'_, Rule>) -> (String, OmlExpr) {
         let mut anno_if_expr = None;
         let mut keys = "".to_string();
@@ -209,19 +330,65 @@ impl OmlExpr {
         (keys, value)
     }
 
-    fn parse_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
+    pub(crate) fn parse_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
         let root_item = root.into_inner().next().unwrap();
         match root_item.as_rule() {
-            Rule::weak_expr => Self::parse_weak_expr(root_item),
+            Rule::let_expr => Self::parse_let_expr(root_item),
+            Rule::lambda_expr => {
+                let mut root_items = root_item.into_inner();
+                let args = Self::parse_lambda_args(root_items.next().unwrap());
+                let body = Box::new(Self::parse_expr(root_items.next().unwrap()));
+                OmlExpr::Lambda((args, body))
+            }
             Rule::op3_expr => Self::parse_op3_expr(root_item),
             _ => unreachable!(),
         }
     }
 
+    fn parse_lambda_args(root: pest::iterators::Pair<'_, Rule>) -> Vec<String> {
+        let mut args = vec![];
+        for root_item in root.into_inner() {
+            match root_item.as_rule() {
+                Rule::id => args.push(root_item.as_str().to_string()),
+                _ => unreachable!(),
+            }
+        }
+        args
+    }
+
+    fn parse_let_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
+        let mut vars = vec![];
+        let mut body = OmlExpr::new();
+        for root_item in root.into_inner() {
+            match root_item.as_rule() {
+                Rule::kw_let | Rule::op_in => (),
+                Rule::let_binding => {
+                    let mut root_items = root_item.into_inner();
+                    let name = root_items.next().unwrap().as_str().to_string();
+                    let value = Self::parse_weak_expr(root_items.next().unwrap());
+                    vars.push((name, value));
+                }
+                Rule::expr => body = Self::parse_expr(root_item),
+                _ => unreachable!(),
+            }
+        }
+        // let a = 1, b = a in body => let a = 1 in let b = a in body
+        while let Some((name, value)) = vars.pop() {
+            body = OmlExpr::Let((name, Box::new(value), Box::new(body)));
+        }
+        body
+    }
+
     fn parse_base_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
         let root_item = root.into_inner().next().unwrap();
         match root_item.as_rule() {
             Rule::literal => Self::parse_literal(root_item),
+            Rule::func_call => {
+                let mut root_items = root_item.into_inner();
+                let name = root_items.next().unwrap().as_str().to_string();
+                let args = Self::parse_exprs(root_items.next().unwrap());
+                OmlExpr::InvokeFunc((Box::new(OmlExpr::None), name, args))
+            }
             Rule::ids => OmlExpr::TempName(Self::parse_ids(root_item)),
             Rule::expr => Self::parse_expr(root_item),
             _ => unreachable!(),
@@ -232,30 +399,114 @@ impl OmlExpr {
         let mut exprs = vec![];
         for root_item in root.into_inner() {
             match root_item.as_rule() {
-                Rule::expr => exprs.push(Self::parse_expr(root_item)),
+                Rule::array_comp => return Self::parse_comprehension(root_item),
+                Rule::array_items => {
+                    for root_item1 in root_item.into_inner() {
+                        match root_item1.as_rule() {
+                            Rule::spread_expr => exprs.push(Self::parse_spread_expr(root_item1)),
+                            Rule::expr => exprs.push(Self::parse_expr(root_item1)),
+                            _ => unreachable!(),
+                        }
+                    }
+                }
                 _ => unreachable!(),
             }
         }
         OmlExpr::Array(exprs)
     }
 
+    fn parse_exprs(root: pest::iterators::Pair<'_, Rule>) -> Vec<OmlExpr> {
+        let mut exprs = vec![];
+        for root_item in root.into_inner() {
+            match root_item.as_rule() {
+                Rule::expr => exprs.push(Self::parse_expr(root_item)),
+                _ => unreachable!(),
+            }
+        }
+        exprs
+    }
+
+    fn parse_spread_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
+        let root_item = root.into_inner().next().unwrap();
+        OmlExpr::Spread(Box::new(Self::parse_expr(root_item)))
+    }
+
     fn parse_map_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
-        let mut map = HashMap::new();
+        let mut parts = vec![];
+        let mut map = IndexMap::new();
         for root_item in root.into_inner() {
             match root_item.as_rule() {
-                Rule::map_assign_pair => {
-                    let (key, value) = Self::parse_assign_pair(root_item);
-                    map.insert(key, value);
+                Rule::map_comp => return Self::parse_comprehension(root_item),
+                Rule::map_items => {
+                    for root_item1 in root_item.into_inner() {
+                        match root_item1.as_rule() {
+                            Rule::spread_expr => {
+                                if !map.is_empty() {
+                                    parts.push(OmlExpr::Map(std::mem::take(&mut map)));
+                                }
+                                parts.push(Self::parse_spread_expr(root_item1));
+                            }
+                            Rule::map_assign_pair => {
+                                let (key, value) = Self::parse_assign_pair(root_item1);
+                                map.insert(key, value);
+                            }
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        if parts.is_empty() {
+            return OmlExpr::Map(map);
+        }
+        if !map.is_empty() {
+            parts.push(OmlExpr::Map(map));
+        }
+        OmlExpr::SpreadMap(parts)
+    }
+
+    fn parse_comprehension(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
+        let mut exprs = vec![];
+        let mut names = vec![];
+        let mut source = OmlExpr::new();
+        let mut cond = None;
+        for root_item in root.into_inner() {
+            match root_item.as_rule() {
+                Rule::expr => exprs.push(Self::parse_expr(root_item)),
+                Rule::comp_for => {
+                    let mut is_source = true;
+                    for root_item1 in root_item.into_inner() {
+                        match root_item1.as_rule() {
+                            Rule::id => names.push(root_item1.as_str().to_string()),
+                            Rule::op_in => (),
+                            Rule::expr if is_source => {
+                                source = Self::parse_expr(root_item1);
+                                is_source = false;
+                            }
+                            Rule::expr => cond = Some(Box::new(Self::parse_expr(root_item1))),
+                            _ => unreachable!(),
+                        }
+                    }
                 }
                 _ => unreachable!(),
             }
         }
-        OmlExpr::Map(map)
+        let value = Box::new(exprs.remove(exprs.len() - 1));
+        let key = exprs.pop().map(Box::new);
+        OmlExpr::Comprehension(OmlExprComprehension {
+            key,
+            value,
+            names,
+            source: Box::new(source),
+            cond,
+        })
     }
 
     fn parse_strong_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
         let root_item = root.into_inner().next().unwrap();
         match root_item.as_rule() {
+            Rule::match_expr => Self::parse_match_expr(root_item),
             Rule::base_expr => Self::parse_base_expr(root_item),
             Rule::array_expr => Self::parse_array_expr(root_item),
             Rule::map_expr => Self::parse_map_expr(root_item),
@@ -263,6 +514,36 @@ impl OmlExpr {
         }
     }
 
+    fn parse_match_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
+        let mut value = OmlExpr::None;
+        let mut arms = vec![];
+        for root_item in root.into_inner() {
+            match root_item.as_rule() {
+                Rule::kw_match => (),
+                Rule::expr => value = Self::parse_expr(root_item),
+                Rule::match_arm => {
+                    let mut patterns = vec![];
+                    let mut expr = OmlExpr::None;
+                    for root_item1 in root_item.into_inner() {
+                        match root_item1.as_rule() {
+                            Rule::match_pattern => {
+                                patterns.push(OmlMatchPattern::parse(root_item1))
+                            }
+                            Rule::expr => expr = Self::parse_expr(root_item1),
+                            _ => unreachable!(),
+                        }
+                    }
+                    arms.push((patterns, expr));
+                }
+                _ => unreachable!(),
+            }
+        }
+        OmlExpr::Match(OmlExprMatch {
+            value: Box::new(value),
+            arms,
+        })
+    }
+
     fn parse_middle_expr(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
         enum SuffixOp {
             AccessVar(String),
@@ -312,7 +593,7 @@ impl OmlExpr {
             }
         }
         while !prefix_ops.is_empty() {
-            let prefix_op = prefix_ops.remove(prefix_ops.len());
+            let prefix_op = prefix_ops.remove(prefix_ops.len() - 1);
             expr = OmlExpr::Op1Prefix((prefix_op, Box::new(expr)));
         }
         while !suffix_ops.is_empty() {
@@ -334,7 +615,10 @@ impl OmlExpr {
         for root_item in root.into_inner() {
             match root_item.as_rule() {
                 Rule::middle_expr => exprs.push(Self::parse_middle_expr(root_item)),
-                Rule::op2 => ops.push(root_item.as_str().to_string()),
+                Rule::op2 => {
+                    let op: Vec<_> = root_item.as_str().split_whitespace().collect();
+                    ops.push(op.join(" "))
+                }
                 _ => unreachable!(),
             }
         }
@@ -345,24 +629,24 @@ impl OmlExpr {
                 (op, level)
             })
             .collect();
-        //
-        for i in 0..10 {
-            if exprs.len() == 1 {
-                break;
-            }
-            if i == 5 {
-                for j in 1..ops.len() {
-                    if ops[j - i].1 == i && ops[j].1 == i {
-                        exprs.insert(j, exprs[j].clone());
-                        ops.insert(j, ("&&".to_string(), get_op2_level("&&")));
-                    }
-                }
+        // a < b < c => a < b && b < c
+        let cmp_level = get_op2_level("<");
+        let mut idx = 1;
+        while idx < ops.len() {
+            if ops[idx - 1].1 == cmp_level && ops[idx].1 == cmp_level {
+                exprs.insert(idx, exprs[idx].clone());
+                ops.insert(idx, ("&&".to_string(), get_op2_level("&&")));
+                idx += 1;
             }
-            for idx in 0..ops.len() {
-                if let Some((_, level)) = ops.get(idx) {
-                    if *level != i {
-                        continue;
-                    }
+            idx += 1;
+        }
+        //
+        for level in 0..=get_op2_level("||") {
+            let mut idx = 0;
+            while idx < ops.len() {
+                if ops[idx].1 != level {
+                    idx += 1;
+                    continue;
                 }
                 let left = exprs.remove(idx);
                 let right = exprs.remove(idx);
@@ -378,10 +662,14 @@ impl OmlExpr {
         let mut exprs = vec![];
         for root_item in root.into_inner() {
             match root_item.as_rule() {
-                Rule::middle_expr => exprs.push(Self::parse_middle_expr(root_item)),
+                Rule::weak_expr => exprs.push(Self::parse_weak_expr(root_item)),
+                Rule::expr => exprs.push(Self::parse_expr(root_item)),
                 _ => unreachable!(),
             }
         }
+        if exprs.len() == 1 {
+            return exprs.remove(0);
+        }
         let expr1 = Box::new(exprs.remove(0));
         let expr2 = Box::new(exprs.remove(0));
         let expr3 = Box::new(exprs.remove(0));
@@ -391,29 +679,47 @@ impl OmlExpr {
     fn parse_literal(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
         let root_item = root.into_inner().next().unwrap();
         OmlExpr::Value(match root_item.as_rule() {
+            Rule::null_literal => OmlValue::None,
             Rule::boolean_literal => OmlValue::Bool(root_item.as_str() == "true"),
-            Rule::number_literal => match root_item.as_str().parse::<i64>() {
-                Ok(n) => OmlValue::Int64(n),
-                Err(_) => OmlValue::String(root_item.as_str().into_base()),
-            },
-            Rule::string_literal => OmlValue::String(root_item.as_str().into_base()),
+            Rule::number_literal => {
+                let s = root_item.as_str();
+                match (s.parse::<i64>(), s.contains('.')) {
+                    (Ok(n), _) => OmlValue::Int64(n),
+                    (Err(_), true) => OmlValue::Float64(s.parse().unwrap()),
+                    // integers out of the i64 range keep their digits
+                    (Err(_), false) => OmlValue::String(s.to_string()),
+                }
+            }
+            Rule::string_literal => {
+                let s = root_item.as_str().into_base();
+                match s.contains("${") {
+                    true => return OmlExpr::EnvString(s),
+                    false => OmlValue::String(s),
+                }
+            }
             Rule::format_string_literal => return Self::parse_format_string_literal(root_item),
             _ => unreachable!(),
         })
     }
 
     fn parse_format_string_literal(root: pest::iterators::Pair<'_, Rule>) -> OmlExpr {
+        let unescape = |s: &str| s.into_base().replace("{{", "{").replace("}}", "}");
         let mut strs = vec![];
-        let mut exprs = vec![];
+        let mut exprs: Vec<(OmlExpr, Option<FormatSpec>)> = vec![];
         for root_item in root.into_inner() {
             match root_item.as_rule() {
                 Rule::format_string => {
-                    return OmlExpr::Value(OmlValue::String(root_item.as_str().into_base()));
+                    return OmlExpr::Value(OmlValue::String(unescape(root_item.as_str())));
+                }
+                Rule::format_string_part1 => strs.push(unescape(root_item.as_str())),
+                Rule::format_string_part2 => strs.push(unescape(root_item.as_str())),
+                Rule::format_string_part3 => strs.push(unescape(root_item.as_str())),
+                Rule::expr => exprs.push((Self::parse_expr(root_item), None)),
+                Rule::format_spec => {
+                    if let Some((_, spec)) = exprs.last_mut() {
+                        *spec = Some(FormatSpec::parse(root_item.as_str()));
+                    }
                 }
-                Rule::format_string_part1 => strs.push(root_item.as_str().into_base()),
-                Rule::format_string_part2 => strs.push(root_item.as_str().into_base()),
-                Rule::format_string_part3 => strs.push(root_item.as_str().into_base()),
-                Rule::expr => exprs.push(Self::parse_expr(root_item)),
                 _ => unreachable!(),
             }
         }
@@ -421,6 +727,9 @@ impl OmlExpr {
     }
 
     fn parse_ids(root: pest::iterators::Pair<'_, Rule>) -> String {
+        if root.as_rule() == Rule::ids {
+            return root.as_str().to_string();
+        }
         let root_item = root.into_inner().next().unwrap();
         match root_item.as_rule() {
             Rule::ids => root_item.as_str().to_string(),
@@ -429,35 +738,41 @@ impl OmlExpr {
         }
     }
 
-    pub fn root_evalute(&self, path: &str) -> Result<OmlValue, String> {
+    pub fn root_evalute(&self, path: &str) -> Result<OmlValue, OmlError> {
+        let _funcs = FuncTableGuard::install(self);
         self[path].evalute_cb(path, &|path| self.root_evalute(path))
     }
 
     pub fn evalute_cb(
         &self,
         path: &str,
-        calc_cb: &impl Fn(&str) -> Result<OmlValue, String>,
-    ) -> Result<OmlValue, String> {
+        calc_cb: &impl Fn(&str) -> Result<OmlValue, OmlError>,
+    ) -> Result<OmlValue, OmlError> {
         Ok(match self {
             OmlExpr::None => OmlValue::None,
             OmlExpr::Value(val) => val.clone(),
+            OmlExpr::EnvString(s) => env_string_expr(s).evalute_cb(path, calc_cb)?,
             OmlExpr::Array(arr) => {
                 let mut ret = vec![];
                 for (index, item) in arr.iter().enumerate() {
                     let val = item.evalute_cb(&path.append_num(index), calc_cb)?;
-                    ret.push(val);
+                    match (item, val) {
+                        (OmlExpr::Spread(_), OmlValue::Array(arr1)) => ret.extend(arr1),
+                        (OmlExpr::Spread(_), val) => return Err(Self::spread_error(val)),
+                        (_, val) => ret.push(val),
+                    }
                 }
                 OmlValue::Array(ret)
             }
             OmlExpr::Map(map) => {
-                let mut ret = HashMap::new();
+                let mut ret = IndexMap::new();
                 for (key, item) in map.iter() {
                     let val = item.evalute_cb(&path.append_str(key), calc_cb)?;
                     ret.insert(key.clone(), val);
                 }
                 OmlValue::Map(ret)
             }
-            OmlExpr::TempName(name) => calc_cb(&path.remove_once().append_str(name))?,
+            OmlExpr::TempName(name) => calc_cb(&path.remove_scope().append_str(name))?,
             OmlExpr::Op1Prefix((name, expr)) => {
                 let val = expr.evalute_cb(path, calc_cb)?;
                 Op1Evaluator::eval_prefix(name, val)?
@@ -476,26 +791,60 @@ impl OmlExpr {
                 let val = match cond.as_bool() {
                     Some(true) => left,
                     Some(false) => right,
-                    None => return Err("condition is not bool".to_string()),
+                    None => return Err(OmlError::Eval("condition is not bool".to_string())),
                 };
                 val.evalute_cb(path, calc_cb)?
             }
             OmlExpr::FormatString((strs, exprs)) => {
                 let mut exprs1 = vec![];
-                for item in exprs.iter() {
+                for (item, spec) in exprs.iter() {
                     let val = item.evalute_cb(path, calc_cb)?;
-                    exprs1.push(val);
+                    exprs1.push((val, spec));
+                }
+                Self::format_string(strs, exprs1)?
+            }
+            OmlExpr::AccessVar((expr, name)) => {
+                let val = expr.evalute_cb(path, calc_cb)?;
+                val.get(name).cloned().unwrap_or(OmlValue::None)
+            }
+            OmlExpr::InvokeFunc((obj, name, args)) => {
+                let eval = |expr: &OmlExpr, path: &str| Ok((expr.evalute_cb(path, calc_cb)?, true));
+                Self::invoke_with(obj, name, args, path, &eval)?.0
+            }
+            OmlExpr::Comprehension(comp) => {
+                let source = comp.source.evalute_cb(path, calc_cb)?;
+                let eval = |expr: &OmlExpr| Ok((expr.evalute_cb(path, calc_cb)?, true));
+                comp.evalute_with(source, eval)?.0
+            }
+            OmlExpr::Match(match_expr) => {
+                let value = match_expr.value.evalute_cb(path, calc_cb)?;
+                match_expr.select(&value)?.evalute_cb(path, calc_cb)?
+            }
+            OmlExpr::Spread(expr) => expr.evalute_cb(path, calc_cb)?,
+            OmlExpr::Let((name, value, body)) => {
+                let value = value.evalute_cb(path, calc_cb)?;
+                body.bind_var(name, &value).evalute_cb(path, calc_cb)?
+            }
+            OmlExpr::LetAnno(expr) if matches!(**expr, OmlExpr::Lambda(_)) => OmlValue::None,
+            OmlExpr::LetAnno(expr) => expr.evalute_cb(path, calc_cb)?,
+            OmlExpr::AssertAnno((cond, message)) => {
+                match cond.evalute_cb(path, calc_cb)?.as_bool() {
+                    Some(true) => OmlValue::Bool(true),
+                    Some(false) => message.evalute_cb(path, calc_cb)?,
+                    None => return Err(OmlError::Eval("condition must be boolean.".to_string())),
                 }
-                exprs1.push(OmlValue::String("".to_string()));
-                let mut ret = "".to_string();
-                for (a, b) in strs.iter().zip(exprs1.iter()) {
-                    ret.push_str(a);
-                    ret.push_str(&b.as_str());
+            }
+            OmlExpr::Lambda(_) => return Err(Self::lambda_error()),
+            OmlExpr::SpreadMap(parts) => {
+                let mut ret = IndexMap::new();
+                for part in parts.iter() {
+                    match part.evalute_cb(path, calc_cb)? {
+                        OmlValue::Map(map) => ret.extend(map),
+                        val => return Err(Self::spread_error(val)),
+                    }
                 }
-                OmlValue::String(ret)
+                OmlValue::Map(ret)
             }
-            OmlExpr::AccessVar(_) => todo!(),
-            OmlExpr::InvokeFunc(_) => todo!(),
             OmlExpr::IfAnno(if_anno) => {
                 let mut ret = None;
                 for (cond, value) in if_anno.exprs.iter() {
@@ -505,7 +854,7 @@ impl OmlExpr {
                             ret = value.evalute_cb(path, calc_cb).ok();
                             break;
                         }
-                        false => return Err("condition is not bool".to_string()),
+                        false => return Err(OmlError::Eval("condition is not bool".to_string())),
                     }
                 }
                 ret.unwrap_or(match &if_anno.default {
@@ -516,46 +865,169 @@ impl OmlExpr {
         })
     }
 
-    pub fn evalute(&self) -> Result<OmlValue, String> {
+    /// Remove `@let` bindings and `@assert` results from an evaluated value, collecting failed asserts
+    fn strip_locals(
+        &self,
+        path: &str,
+        value: &mut OmlValue,
+        failures: &mut Vec<(String, usize, String)>,
+    ) {
+        match (self, value) {
+            (OmlExpr::Map(map), OmlValue::Map(value_map)) => {
+                for (key, expr) in map.iter() {
+                    match expr {
+                        OmlExpr::LetAnno(_) => {
+                            value_map.shift_remove(key);
+                        }
+                        OmlExpr::AssertAnno(_) => match value_map.shift_remove(key) {
+                            Some(OmlValue::Bool(true)) => (),
+                            Some(message) => {
+                                // keys are `@assert{offset}`, with a `_{n}` suffix once layered
+                                let offset = key["@assert".len()..].split('_').next().unwrap();
+                                let offset = offset.parse().unwrap_or_default();
+                                failures.push((path.to_string(), offset, message.as_str()))
+                            }
+                            None => (),
+                        },
+                        _ => {
+                            if let Some(value) = value_map.get_mut(key) {
+                                expr.strip_locals(&path.append_str(key), value, failures);
+                            }
+                        }
+                    }
+                }
+            }
+            (OmlExpr::Array(arr), OmlValue::Array(value_arr)) => {
+                for (index, (expr, value)) in arr.iter().zip(value_arr.iter_mut()).enumerate() {
+                    expr.strip_locals(&path.append_num(index), value, failures);
+                }
+            }
+            (OmlExpr::IfAnno(if_anno), value) => {
+                for (_, expr) in if_anno.exprs.iter() {
+                    expr.strip_locals(path, value, failures);
+                }
+                if let Some(expr) = &if_anno.default {
+                    expr.strip_locals(path, value, failures);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn check_asserts(&self, path: &str, value: &mut OmlValue) -> Result<(), OmlError> {
+        let mut failures = vec![];
+        self.strip_locals(path, value, &mut failures);
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(OmlError::Assert(failures)),
+        }
+    }
+
+    fn lambda_error() -> OmlError {
+        OmlError::Eval("lambda must be called.".to_string())
+    }
+
+    fn spread_error(val: OmlValue) -> OmlError {
+        match val {
+            OmlValue::None => OmlError::NoneOperand("...".to_string()),
+            _ => OmlError::Eval("illegal spread value.".to_string()),
+        }
+    }
+
+    fn format_string(
+        strs: &[String],
+        vals: Vec<(OmlValue, &Option<FormatSpec>)>,
+    ) -> Result<OmlValue, OmlError> {
+        let mut ret = "".to_string();
+        let mut vals = vals.into_iter();
+        for a in strs.iter() {
+            ret.push_str(a);
+            match vals.next() {
+                Some((b, Some(spec))) => ret.push_str(&spec.format(&b)?),
+                Some((b, None)) => ret.push_str(&b.as_str()),
+                None => (),
+            }
+        }
+        Ok(OmlValue::String(ret))
+    }
+
+    pub fn evalute(&self) -> Result<OmlValue, OmlError> {
+        let _funcs = FuncTableGuard::install(self);
         let mut last_result = OmlValue::None;
         let mut count = 3;
         while count >= 0 {
             count -= 1;
             match self.evalute2("", &last_result)? {
-                (result, true) => return Ok(result),
+                (mut result, true) => {
+                    self.check_asserts("", &mut result)?;
+                    return Ok(result);
+                }
                 (result, false) => last_result = result,
             }
         }
-        Err("evalute failed.".to_string())
+        Err(OmlError::Eval("evalute failed.".to_string()))
     }
 
-    fn evalute2(&self, path: &str, last_result: &OmlValue) -> Result<(OmlValue, bool), String> {
+    /// Evaluate with the environment variables allowed by `options` visible to `env()`
+    pub fn evalute_with_env(&self, options: &EnvOptions) -> Result<OmlValue, OmlError> {
+        let _env = EnvGuard::install(options);
+        self.evalute()
+    }
+
+    /// Whether a condition at `path` holds against an evaluated value, as an `@if` would take it
+    pub(crate) fn holds(&self, path: &str, value: &OmlValue) -> bool {
+        matches!(self.evalute2(path, value), Ok((OmlValue::Bool(true), true)))
+    }
+
+    fn evalute2(&self, path: &str, last_result: &OmlValue) -> Result<(OmlValue, bool), OmlError> {
         let mut success = true;
         let value = match self {
             OmlExpr::None => OmlValue::None,
             OmlExpr::Value(val) => val.clone(),
+            OmlExpr::EnvString(s) => {
+                let (val, tmp_success) = env_string_expr(s).evalute2(path, last_result)?;
+                success &= tmp_success;
+                val
+            }
             OmlExpr::Array(arr) => {
                 let mut ret = vec![];
                 for (index, item) in arr.iter().enumerate() {
                     let new_path = path.append_num(index);
-                    let (val, tmp_success) = item.evalute2(&new_path, last_result)?;
-                    ret.push(val);
+                    let (val, tmp_success) =
+                        (item.evalute2(&new_path, last_result)).map_err(|err| err.at(&new_path))?;
                     success &= tmp_success;
+                    match (item, val) {
+                        (OmlExpr::Spread(_), OmlValue::Array(arr1)) => ret.extend(arr1),
+                        (OmlExpr::Spread(_), val) if tmp_success => {
+                            return Err(Self::spread_error(val))
+                        }
+                        (OmlExpr::Spread(_), _) => (),
+                        (_, val) => ret.push(val),
+                    }
                 }
                 OmlValue::Array(ret)
             }
             OmlExpr::Map(map) => {
-                let mut ret = HashMap::new();
+                let mut ret = IndexMap::new();
                 for (key, value) in map.iter() {
                     let new_path = path.append_str(key);
-                    let (val, tmp_success) = value.evalute2(&new_path, last_result)?;
-                    ret.insert(key.clone(), val);
+                    let (val, tmp_success) = (value.evalute2(&new_path, last_result))
+                        .map_err(|err| err.at(&new_path))?;
+                    // unresolved values are left out so that the next pass can't mistake them for none,
+                    // except for maps and arrays of maps whose resolved members are still usable
+                    let is_group_array = match value {
+                        OmlExpr::Array(arr) => arr.iter().all(|item| item.is_map()),
+                        _ => false,
+                    };
+                    if tmp_success || val.is_map() || is_group_array {
+                        ret.insert(key.clone(), val);
+                    }
                     success &= tmp_success;
                 }
                 OmlValue::Map(ret)
             }
             OmlExpr::TempName(name) => {
-                match last_result.get(&path.remove_once().append_str(name)) {
+                match last_result.get(&path.remove_scope().append_str(name)) {
                     Some(val) => val.clone(),
                     None => {
                         success = false;
@@ -598,37 +1070,115 @@ impl OmlExpr {
                     let (value, tmp_success) = match cond.as_bool() {
                         Some(true) => left.evalute2(path, last_result)?,
                         Some(false) => right.evalute2(path, last_result)?,
-                        None => return Err("condition must be boolean.".to_string()),
+                        None => {
+                            return Err(OmlError::Eval("condition must be boolean.".to_string()))
+                        }
                     };
                     success &= tmp_success;
                     value
                 } else {
+                    success = false;
+                    OmlValue::None
+                }
+            }
+            OmlExpr::Match(match_expr) => {
+                let (value, tmp_success) = match_expr.value.evalute2(path, last_result)?;
+                if tmp_success {
+                    let (value, tmp_success) =
+                        match_expr.select(&value)?.evalute2(path, last_result)?;
+                    success &= tmp_success;
+                    value
+                } else {
+                    success = false;
                     OmlValue::None
                 }
             }
             OmlExpr::FormatString((strs, exprs)) => {
                 let mut exprs1 = vec![];
                 let mut tmp_success = true;
-                for item in exprs.iter() {
+                for (item, spec) in exprs.iter() {
                     let (val, tmp_success1) = item.evalute2(path, last_result)?;
-                    exprs1.push(val);
+                    exprs1.push((val, spec));
                     tmp_success &= tmp_success1;
                 }
                 if tmp_success {
-                    exprs1.push(OmlValue::String("".to_string()));
-                    let mut ret = "".to_string();
-                    for (a, b) in strs.iter().zip(exprs1.iter()) {
-                        ret.push_str(a);
-                        ret.push_str(&b.as_str());
+                    Self::format_string(strs, exprs1)?
+                } else {
+                    success = false;
+                    OmlValue::None
+                }
+            }
+            OmlExpr::AccessVar((expr, name)) => {
+                let (val, tmp_success) = expr.evalute2(path, last_result)?;
+                success &= tmp_success;
+                val.get(name).cloned().unwrap_or(OmlValue::None)
+            }
+            OmlExpr::InvokeFunc((obj, name, args)) => {
+                let eval = |expr: &OmlExpr, path: &str| expr.evalute2(path, last_result);
+                let (val, tmp_success) = Self::invoke_with(obj, name, args, path, &eval)?;
+                success &= tmp_success;
+                val
+            }
+            OmlExpr::Comprehension(comp) => {
+                let (source, tmp_success) = comp.source.evalute2(path, last_result)?;
+                if tmp_success {
+                    let eval = |expr: &OmlExpr| expr.evalute2(path, last_result);
+                    let (value, tmp_success) = comp.evalute_with(source, eval)?;
+                    success &= tmp_success;
+                    value
+                } else {
+                    success = false;
+                    OmlValue::None
+                }
+            }
+            OmlExpr::LetAnno(expr) if matches!(**expr, OmlExpr::Lambda(_)) => OmlValue::None,
+            OmlExpr::Lambda(_) => return Err(Self::lambda_error()),
+            OmlExpr::Spread(expr) | OmlExpr::LetAnno(expr) => {
+                let (val, tmp_success) = expr.evalute2(path, last_result)?;
+                success &= tmp_success;
+                val
+            }
+            OmlExpr::AssertAnno((cond, message)) => {
+                let (cond, tmp_success) = cond.evalute2(path, last_result)?;
+                success &= tmp_success;
+                match (tmp_success, cond.as_bool()) {
+                    (false, _) => OmlValue::None,
+                    (true, Some(true)) => OmlValue::Bool(true),
+                    (true, Some(false)) => {
+                        let (message, tmp_success) = message.evalute2(path, last_result)?;
+                        success &= tmp_success;
+                        message
+                    }
+                    (true, None) => {
+                        return Err(OmlError::Eval("condition must be boolean.".to_string()))
                     }
-                    OmlValue::String(ret)
+                }
+            }
+            OmlExpr::Let((name, value, body)) => {
+                let (value, tmp_success) = value.evalute2(path, last_result)?;
+                if tmp_success {
+                    let (val, tmp_success) =
+                        body.bind_var(name, &value).evalute2(path, last_result)?;
+                    success &= tmp_success;
+                    val
                 } else {
                     success = false;
                     OmlValue::None
                 }
             }
-            OmlExpr::AccessVar(_) => todo!(),
-            OmlExpr::InvokeFunc(_) => todo!(),
+            OmlExpr::SpreadMap(parts) => {
+                let mut ret = IndexMap::new();
+                for part in parts.iter() {
+                    let (val, tmp_success) = part.evalute2(path, last_result)?;
+                    success &= tmp_success;
+                    match val {
+                        OmlValue::Map(map) => ret.extend(map),
+                        val if tmp_success => return Err(Self::spread_error(val)),
+                        _ => (),
+                    }
+                }
+                OmlValue::Map(ret)
+            }
             OmlExpr::IfAnno(if_anno) => {
                 for (cond, value) in if_anno.exprs.iter() {
                     let (cond_val, tmp_success) = cond.evalute2(path, last_result)?;
@@ -652,14 +1202,317 @@ impl OmlExpr {
     }
 }
 
+impl OmlExpr {
+    /// Replace references to a local variable with its value
+    pub(crate) fn bind_var(&self, name: &str, value: &OmlValue) -> OmlExpr {
+        self.replace_var(name, &|sub_path| match sub_path {
+            "" => OmlExpr::Value(value.clone()),
+            _ => OmlExpr::Value(value.get(sub_path).cloned().unwrap_or(OmlValue::None)),
+        })
+    }
+
+    /// Replace references to a local variable, `to` receives the sub path after the name
+    fn replace_var(&self, name: &str, to: &dyn Fn(&str) -> OmlExpr) -> OmlExpr {
+        let bind = |expr: &OmlExpr| expr.replace_var(name, to);
+        let bind_box = |expr: &OmlExpr| Box::new(expr.replace_var(name, to));
+        match self {
+            OmlExpr::None | OmlExpr::Value(_) | OmlExpr::EnvString(_) => self.clone(),
+            OmlExpr::Array(arr) => OmlExpr::Array(arr.iter().map(bind).collect()),
+            OmlExpr::Map(map) => {
+                OmlExpr::Map(map.iter().map(|(k, v)| (k.clone(), bind(v))).collect())
+            }
+            OmlExpr::TempName(tmp_name) if tmp_name == name => to(""),
+            OmlExpr::TempName(tmp_name) => {
+                match tmp_name
+                    .strip_prefix(name)
+                    .and_then(|s| s.strip_prefix('.'))
+                {
+                    Some(sub_path) => to(sub_path),
+                    None => self.clone(),
+                }
+            }
+            OmlExpr::Op1Prefix((op, expr)) => OmlExpr::Op1Prefix((op.clone(), bind_box(expr))),
+            OmlExpr::Op1Suffix((expr, op)) => OmlExpr::Op1Suffix((bind_box(expr), op.clone())),
+            OmlExpr::Op2((left, op, right)) => {
+                OmlExpr::Op2((bind_box(left), op.clone(), bind_box(right)))
+            }
+            OmlExpr::Op3((cond, left, right)) => {
+                OmlExpr::Op3((bind_box(cond), bind_box(left), bind_box(right)))
+            }
+            OmlExpr::Match(match_expr) => OmlExpr::Match(OmlExprMatch {
+                value: bind_box(&match_expr.value),
+                arms: (match_expr.arms.iter())
+                    .map(|(patterns, expr)| (patterns.clone(), bind(expr)))
+                    .collect(),
+            }),
+            OmlExpr::FormatString((strs, exprs)) => {
+                let exprs = exprs.iter().map(|(e, spec)| (bind(e), spec.clone()));
+                OmlExpr::FormatString((strs.clone(), exprs.collect()))
+            }
+            OmlExpr::AccessVar((expr, var)) => OmlExpr::AccessVar((bind_box(expr), var.clone())),
+            OmlExpr::InvokeFunc((obj, func, args)) => {
+                OmlExpr::InvokeFunc((bind_box(obj), func.clone(), args.iter().map(bind).collect()))
+            }
+            OmlExpr::IfAnno(if_anno) => OmlExpr::IfAnno(OmlExprIfAnno {
+                exprs: if_anno
+                    .exprs
+                    .iter()
+                    .map(|(c, v)| (bind(c), bind(v)))
+                    .collect(),
+                default: if_anno.default.as_ref().map(|v| bind_box(v)),
+            }),
+            OmlExpr::Comprehension(comp) => {
+                let mut comp1 = comp.clone();
+                comp1.source = bind_box(&comp.source);
+                if !comp.names.iter().any(|n| n == name) {
+                    comp1.key = comp.key.as_ref().map(|v| bind_box(v));
+                    comp1.value = bind_box(&comp.value);
+                    comp1.cond = comp.cond.as_ref().map(|v| bind_box(v));
+                }
+                OmlExpr::Comprehension(comp1)
+            }
+            OmlExpr::Spread(expr) => OmlExpr::Spread(bind_box(expr)),
+            OmlExpr::SpreadMap(parts) => OmlExpr::SpreadMap(parts.iter().map(bind).collect()),
+            OmlExpr::Let((var, expr, body)) => {
+                let body = match var == name {
+                    true => body.clone(),
+                    false => bind_box(body),
+                };
+                OmlExpr::Let((var.clone(), bind_box(expr), body))
+            }
+            OmlExpr::LetAnno(expr) => OmlExpr::LetAnno(bind_box(expr)),
+            OmlExpr::AssertAnno((cond, message)) => {
+                OmlExpr::AssertAnno((bind_box(cond), bind_box(message)))
+            }
+            OmlExpr::Lambda((args, body)) => {
+                let body = match args.iter().any(|arg| arg == name) {
+                    true => body.clone(),
+                    false => bind_box(body),
+                };
+                OmlExpr::Lambda((args.clone(), body))
+            }
+        }
+    }
+}
+
+/// `@template name(params) { ... }`, expanded into a plain map at every instantiation
+#[derive(Debug, Clone)]
+struct OmlTemplate {
+    params: Vec<(String, Option<OmlExpr>)>,
+    body: IndexMap<String, OmlExpr>,
+}
+
+impl OmlTemplate {
+    fn parse(root: pest::iterators::Pair<'_, Rule>) -> (String, Self) {
+        let mut root_items = root.into_inner();
+        let name = root_items.next().unwrap().as_str().to_string();
+        let mut params = vec![];
+        for root_item in root_items.next().unwrap().into_inner() {
+            let mut root_items1 = root_item.into_inner();
+            let param = root_items1.next().unwrap().as_str().to_string();
+            params.push((param, root_items1.next().map(OmlExpr::parse_expr)));
+        }
+        let mut body = IndexMap::new();
+        for root_item in root_items {
+            OmlExpr::parse_group_pair(&mut body, root_item);
+        }
+        (name, Self { params, body })
+    }
+
+    fn expand(
+        &self,
+        name: &str,
+        head: &str,
+        mut args: IndexMap<String, OmlExpr>,
+    ) -> Result<OmlExpr, OmlError> {
+        if let Some(arg) = args
+            .keys()
+            .find(|arg| !self.params.iter().any(|(p, _)| p == *arg))
+        {
+            return Err(OmlError::Parse(format!(
+                "unknown argument {} for template {}.",
+                arg, name
+            )));
+        }
+        let mut ret = OmlExpr::Map(self.body.clone());
+        let mut locals = IndexMap::new();
+        for (param, default) in self.params.iter() {
+            let value = match (args.shift_remove(param), default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.clone(),
+                (None, None) => {
+                    return Err(OmlError::Parse(format!(
+                        "missing argument {} for template {}.",
+                        param, name
+                    )))
+                }
+            };
+            // parameters live in hidden locals of the instance, so nested keys can reach them too
+            let local = format!("@{}", param);
+            let local_path = format!("root.{}.{}", head, local);
+            ret = ret.replace_var(param, &|sub_path| match sub_path {
+                "" => OmlExpr::TempName(local_path.clone()),
+                _ => OmlExpr::TempName(format!("{}.{}", local_path, sub_path)),
+            });
+            locals.insert(local, OmlExpr::LetAnno(Box::new(value)));
+        }
+        ret.apply(OmlExpr::Map(locals));
+        Ok(ret)
+    }
+}
+
+impl OmlExprMatch {
+    /// Pick the expression of the first arm matching `value`
+    fn select(&self, value: &OmlValue) -> Result<&OmlExpr, OmlError> {
+        for (patterns, expr) in self.arms.iter() {
+            if patterns.iter().any(|pattern| pattern.matches(value)) {
+                return Ok(expr);
+            }
+        }
+        Err(OmlError::NonExhaustive(value.as_str()))
+    }
+}
+
+impl OmlMatchPattern {
+    fn parse(root: pest::iterators::Pair<'_, Rule>) -> Self {
+        let parse_literal = |root_item| match OmlExpr::parse_literal(root_item) {
+            OmlExpr::Value(val) => val,
+            // patterns are constant, `${NAME}` is kept as written
+            OmlExpr::EnvString(s) => OmlValue::String(s),
+            _ => unreachable!(),
+        };
+        let root_item = root.into_inner().next().unwrap();
+        match root_item.as_rule() {
+            Rule::match_wildcard => OmlMatchPattern::Wildcard,
+            Rule::match_literal => OmlMatchPattern::Value(parse_literal(root_item)),
+            Rule::match_range => {
+                let (mut start, mut end, mut inclusive) = (None, None, None);
+                for root_item1 in root_item.into_inner() {
+                    match root_item1.as_rule() {
+                        Rule::match_range_op => inclusive = Some(root_item1.as_str() == "..="),
+                        Rule::match_literal if inclusive.is_none() => {
+                            start = Some(parse_literal(root_item1))
+                        }
+                        Rule::match_literal => end = Some(parse_literal(root_item1)),
+                        _ => unreachable!(),
+                    }
+                }
+                OmlMatchPattern::Range((start, end, inclusive == Some(true)))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn matches(&self, value: &OmlValue) -> bool {
+        // values that can't be compared with the pattern simply don't match
+        let cmp = |op: &str, other: &OmlValue| {
+            Op2Evaluator::eval(value.clone(), op, other.clone())
+                .map(|ret| ret.as_bool() == Some(true))
+                .unwrap_or(false)
+        };
+        match self {
+            OmlMatchPattern::Value(val) => cmp("==", val),
+            OmlMatchPattern::Range((start, end, inclusive)) => {
+                let end_op = if *inclusive { "<=" } else { "<" };
+                start.as_ref().map(|start| cmp(">=", start)).unwrap_or(true)
+                    && end.as_ref().map(|end| cmp(end_op, end)).unwrap_or(true)
+            }
+            OmlMatchPattern::Wildcard => true,
+        }
+    }
+}
+
+impl OmlExprComprehension {
+    fn evalute_with(
+        &self,
+        source: OmlValue,
+        eval: impl Fn(&OmlExpr) -> Result<(OmlValue, bool), OmlError>,
+    ) -> Result<(OmlValue, bool), OmlError> {
+        let items: Vec<Vec<OmlValue>> = match (source, self.names.len()) {
+            (OmlValue::Array(arr), 1) => arr.into_iter().map(|v| vec![v]).collect(),
+            (OmlValue::Array(arr), _) => (arr.into_iter().enumerate())
+                .map(|(i, v)| vec![OmlValue::Int64(i as i64), v])
+                .collect(),
+            (OmlValue::Map(map), 1) => map.into_keys().map(|k| vec![OmlValue::String(k)]).collect(),
+            (OmlValue::Map(map), _) => (map.into_iter())
+                .map(|(k, v)| vec![OmlValue::String(k), v])
+                .collect(),
+            (OmlValue::None, _) => return Err(OmlError::NoneOperand("for".to_string())),
+            _ => {
+                return Err(OmlError::Eval(
+                    "for source must be array or map.".to_string(),
+                ))
+            }
+        };
+        let mut success = true;
+        let mut arr = vec![];
+        let mut map = IndexMap::new();
+        for item in items.into_iter() {
+            let bind = |expr: &OmlExpr| {
+                let vars = self.names.iter().zip(item.iter());
+                vars.fold(expr.clone(), |expr, (name, val)| expr.bind_var(name, val))
+            };
+            if let Some(cond) = &self.cond {
+                let (cond, tmp_success) = eval(&bind(cond))?;
+                success &= tmp_success;
+                match (tmp_success, cond.as_bool()) {
+                    (false, _) | (true, Some(false)) => continue,
+                    (true, Some(true)) => (),
+                    (true, None) => {
+                        return Err(OmlError::Eval("condition must be boolean.".to_string()))
+                    }
+                }
+            }
+            let (value, tmp_success) = eval(&bind(&self.value))?;
+            success &= tmp_success;
+            match &self.key {
+                Some(key) => {
+                    let key = match eval(&bind(key))? {
+                        (OmlValue::String(key), _) => key,
+                        (key @ (OmlValue::Int64(_) | OmlValue::Bool(_)), _) => key.as_str(),
+                        (_, false) => {
+                            success = false;
+                            continue;
+                        }
+                        _ => return Err(OmlError::Eval("map key must be string.".to_string())),
+                    };
+                    map.insert(key, value);
+                }
+                None => arr.push(value),
+            }
+        }
+        Ok(match self.key {
+            Some(_) => (OmlValue::Map(map), success),
+            None => (OmlValue::Array(arr), success),
+        })
+    }
+}
+
+impl From<OmlValue> for OmlExpr {
+    fn from(val: OmlValue) -> Self {
+        match val {
+            OmlValue::None => OmlExpr::None,
+            OmlValue::Array(arr) => OmlExpr::Array(arr.into_iter().map(Self::from).collect()),
+            OmlValue::Map(map) => {
+                OmlExpr::Map(map.into_iter().map(|(k, v)| (k, Self::from(v))).collect())
+            }
+            val => OmlExpr::Value(val),
+        }
+    }
+}
+
 pub(crate) trait PathAppendExt {
     fn append_str(&self, path: &str) -> String;
     fn append_num(&self, num: usize) -> String;
     fn remove_once(&self) -> &str;
+    fn remove_scope(&self) -> &str;
 }
 
 impl PathAppendExt for str {
     fn append_str(&self, name: &str) -> String {
+        if let Some((prefix @ ("root" | "super"), name)) = name.split_once('.') {
+            return self.append_str(prefix).append_str(name);
+        }
         match name {
             "root" => "".to_string(),
             "super" => self.remove_once().to_string(),
@@ -683,6 +1536,21 @@ impl PathAppendExt for str {
             None => "",
         }
     }
+
+    fn remove_scope(&self) -> &str {
+        // array items belong to the same scope as the array itself
+        let mut path = self;
+        while path
+            .rsplit('.')
+            .next()
+            .unwrap_or("")
+            .parse::<usize>()
+            .is_ok()
+        {
+            path = path.remove_once();
+        }
+        path.remove_once()
+    }
 }
 
 impl Index<usize> for OmlExpr {
@@ -786,7 +1654,7 @@ impl OmlExpr {
                 let map = match obj_ref {
                     OmlExpr::Map(map) => map,
                     _ => {
-                        *obj_ref = OmlExpr::Map(HashMap::new());
+                        *obj_ref = OmlExpr::Map(IndexMap::new());
                         match obj_ref {
                             OmlExpr::Map(map) => map,
                             _ => panic!(),
@@ -828,6 +1696,11 @@ impl OmlExpr {
         *self = OmlExpr::Value(OmlValue::String(val.into()));
     }
 
+    /// Replace the node with an evaluated value, arrays and maps stay indexable
+    pub fn set_value(&mut self, val: OmlValue) {
+        *self = val.into();
+    }
+
     pub fn wrap(&self) -> OmlExprWrap {
         OmlExprWrap {
             expr: self,
@@ -888,8 +1761,10 @@ impl<'a> IndexMut<&str> for OmlExprWrap<'a> {
 }
 
 impl<'a> OmlExprWrap<'a> {
-    pub fn evalute(&self) -> Result<OmlValue, String> {
+    pub fn evalute(&self) -> Result<OmlValue, OmlError> {
         let path = unsafe { &*self.path.get() }.clone();
-        self.expr.root_evalute(&path[..])
+        let mut ret = self.expr.root_evalute(&path[..])?;
+        self.expr[&path[..]].check_asserts(&path[..], &mut ret)?;
+        Ok(ret)
     }
 }
@@ -0,0 +1,436 @@
+use super::oml_cst::{OmlCstKind, OmlCstNode, OmlDocument};
+use super::oml_error::OmlError;
+use super::oml_expr::{OmlExpr, OmlParser, Rule};
+use super::oml_printer::write_expr;
+use pest::Parser;
+
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Spaces used for template bodies and items of multi-line arrays and maps
+    pub indent: usize,
+    /// Sort the pairs of each group by key, locals keep their place
+    pub sort_keys: bool,
+    /// Blank lines between groups
+    pub group_spacing: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: 4,
+            sort_keys: false,
+            group_spacing: 1,
+        }
+    }
+}
+
+/// Rewrite `.oml` source in the canonical layout, comments are kept
+pub fn format(src: &str, options: FormatOptions) -> Result<String, OmlError> {
+    let doc = OmlDocument::parse(src)?;
+    Formatter {
+        doc: &doc,
+        options: &options,
+    }
+    .document()
+}
+
+/// A statement with the comments around it
+struct Item<'a> {
+    node: &'a OmlCstNode,
+    /// (blank line before, comment)
+    leading: Vec<(bool, &'a str)>,
+    trailing: Option<&'a str>,
+    blank_before: bool,
+}
+
+struct Formatter<'a> {
+    doc: &'a OmlDocument,
+    options: &'a FormatOptions,
+}
+
+impl<'a> Formatter<'a> {
+    fn text(&self, node: &OmlCstNode) -> &'a str {
+        self.doc.text(node)
+    }
+
+    fn is_comment(&self, node: &OmlCstNode) -> bool {
+        node.kind() == OmlCstKind::Trivia && !self.text(node).trim().is_empty()
+    }
+
+    /// Group statements with their comments, a comment on the line of a statement trails it
+    fn items(
+        &self,
+        nodes: &'a [OmlCstNode],
+        is_item: impl Fn(&OmlCstNode) -> bool,
+    ) -> (Vec<Item<'a>>, Vec<(bool, &'a str)>) {
+        let mut items: Vec<Item<'a>> = vec![];
+        let mut pending = vec![];
+        let mut blank = false;
+        let mut same_line = false;
+        // start of the trailing comment of the last item
+        let mut trailing_start = 0;
+        for node in nodes.iter() {
+            if is_item(node) {
+                items.push(Item {
+                    node,
+                    leading: std::mem::take(&mut pending),
+                    trailing: None,
+                    blank_before: blank,
+                });
+                blank = false;
+                same_line = true;
+            } else if self.is_comment(node) {
+                let comment = self.text(node).trim_end();
+                match items.last_mut() {
+                    Some(item) if same_line && item.trailing.is_none() && pending.is_empty() => {
+                        trailing_start = node.range().start;
+                        item.trailing = Some(comment)
+                    }
+                    // more comments after a block comment stay on the statement's line
+                    Some(item) if same_line && item.trailing.is_some() && pending.is_empty() => {
+                        let range = trailing_start..node.range().end;
+                        item.trailing = Some(self.doc.text_at(range).trim_end())
+                    }
+                    _ => pending.push((blank, comment)),
+                }
+                blank = false;
+                // a line comment ends its line, a block comment doesn't
+                same_line = !comment.starts_with("//");
+            } else if node.kind() == OmlCstKind::Trivia {
+                let newlines = self.text(node).matches('\n').count();
+                if newlines > 0 {
+                    same_line = false;
+                }
+                blank |= newlines > 1;
+            }
+        }
+        (items, pending)
+    }
+
+    /// Comments inside a statement, outside of its values, are moved above it
+    fn inner_comments(&self, node: &'a OmlCstNode, ret: &mut Vec<(bool, &'a str)>) {
+        for child in node.children().iter() {
+            if self.is_comment(child) {
+                ret.push((false, self.text(child).trim_end()));
+            }
+            self.inner_comments(child, ret);
+        }
+    }
+
+    fn has_comment(text: &str) -> Result<bool, OmlError> {
+        fn leaves(pair: pest::iterators::Pair<'_, Rule>, ret: &mut Vec<(usize, usize)>) {
+            let span = pair.as_span();
+            let mut inner = pair.into_inner().peekable();
+            match inner.peek() {
+                Some(_) => inner.for_each(|pair| leaves(pair, ret)),
+                None => ret.push((span.start(), span.end())),
+            }
+        }
+        let mut spans = vec![];
+        for pair in
+            OmlParser::parse(Rule::expr, text).map_err(|err| OmlError::Parse(err.to_string()))?
+        {
+            leaves(pair, &mut spans);
+        }
+        // literals are leaves, so comment markers can only show up between them
+        let mut pos = 0;
+        for (start, end) in spans.into_iter().chain([(text.len(), text.len())]) {
+            let gap = &text[pos..start];
+            if gap.contains("//") || gap.contains("/*") {
+                return Ok(true);
+            }
+            pos = end;
+        }
+        Ok(false)
+    }
+
+    fn parse_value(text: &str) -> Result<OmlExpr, OmlError> {
+        match OmlParser::parse(Rule::expr, text) {
+            Ok(mut root) => Ok(OmlExpr::parse_expr(root.next().unwrap())),
+            Err(err) => Err(OmlError::Parse(err.to_string())),
+        }
+    }
+
+    /// Values written over several lines keep one item per line
+    fn value(&self, node: &OmlCstNode, indent: &str) -> Result<String, OmlError> {
+        let text = self.text(node);
+        if Self::has_comment(text)? {
+            return Ok(text.to_string());
+        }
+        let expr = Self::parse_value(text)?;
+        let item_indent = format!("{}{}", indent, " ".repeat(self.options.indent));
+        let lines = |open: &str, items: Vec<String>, close: &str| {
+            let items: Vec<_> = items
+                .iter()
+                .map(|item| format!("{}{}", item_indent, item))
+                .collect();
+            format!("{}\n{}\n{}{}", open, items.join(",\n"), indent, close)
+        };
+        Ok(match &expr {
+            OmlExpr::Array(arr) if !arr.is_empty() && text.contains('\n') => {
+                let items: Result<Vec<_>, _> = arr.iter().map(write_expr).collect();
+                lines("[", items?, "]")
+            }
+            OmlExpr::Map(map) if !map.is_empty() && text.contains('\n') => {
+                let mut items = vec![];
+                for (key, item) in map.iter() {
+                    items.push(format!("{}: {}", key, write_expr(item)?));
+                }
+                lines("{", items, "}")
+            }
+            expr => write_expr(expr)?,
+        })
+    }
+
+    fn keys(&self, node: &'a OmlCstNode) -> Vec<&'a str> {
+        (node.children().iter())
+            .filter(|node| node.kind() == OmlCstKind::Key)
+            .map(|node| self.text(node))
+            .collect()
+    }
+
+    fn values(&self, node: &'a OmlCstNode) -> Vec<&'a OmlCstNode> {
+        (node.children().iter())
+            .filter(|node| node.kind() == OmlCstKind::Value)
+            .collect()
+    }
+
+    fn first_token(&self, node: &OmlCstNode) -> &'a str {
+        (node.children().iter())
+            .find(|node| node.kind() == OmlCstKind::Token)
+            .map(|node| self.text(node))
+            .unwrap_or("")
+    }
+
+    fn annotation(&self, node: &'a OmlCstNode, indent: &str) -> Result<String, OmlError> {
+        match self.values(node).first() {
+            Some(cond) => Ok(format!("@if {} ", self.value(cond, indent)?)),
+            None => Ok("".to_string()),
+        }
+    }
+
+    /// `key = value` and local statements
+    fn pair(&self, node: &'a OmlCstNode, indent: &str) -> Result<String, OmlError> {
+        let keys = self.keys(node);
+        let values = self.values(node);
+        let value = |index: usize| match values.get(index) {
+            Some(value) => self.value(value, indent),
+            None => Ok("".to_string()),
+        };
+        Ok(match (node.kind(), self.first_token(node)) {
+            (OmlCstKind::Local, "@let") => format!("@let {} = {}", keys[0], value(0)?),
+            (OmlCstKind::Local, "@fn") => {
+                let args = match keys.len() {
+                    2 => keys[1].to_string(),
+                    _ => format!("({})", keys[1..].join(", ")),
+                };
+                format!("@fn {} {} = {}", keys[0], args, value(0)?)
+            }
+            (OmlCstKind::Local, _) => match values.len() {
+                1 => format!("@assert {}", value(0)?),
+                _ => format!("@assert {}, {}", value(0)?, value(1)?),
+            },
+            _ => {
+                let anno = node
+                    .children()
+                    .iter()
+                    .find(|node| node.kind() == OmlCstKind::Annotation);
+                let anno = match anno {
+                    Some(anno) => self.annotation(anno, indent)?,
+                    None => "".to_string(),
+                };
+                format!("{}{} = {}", anno, keys[0], value(0)?)
+            }
+        })
+    }
+
+    fn head(&self, node: &'a OmlCstNode) -> Result<String, OmlError> {
+        let keys = self.keys(node);
+        if self.text(node).starts_with("[[") {
+            return Ok(format!("[[{}]]", keys[0]));
+        }
+        if keys.len() == 1 {
+            return Ok(format!("[{}]", keys[0]));
+        }
+        // template instance, arguments are key and value pairs after the template name
+        let mut args = vec![];
+        for (key, value) in keys[2..].iter().zip(self.values(node)) {
+            args.push(format!("{}: {}", key, self.value(value, "")?));
+        }
+        Ok(format!("[{}] = {}({})", keys[0], keys[1], args.join(", ")))
+    }
+
+    /// Write statements with their comments, `sort` reorders the pairs among themselves
+    fn lines(
+        &self,
+        ret: &mut Vec<String>,
+        mut items: Vec<Item<'a>>,
+        indent: &str,
+        sort: bool,
+    ) -> Result<(), OmlError> {
+        if sort {
+            let slots: Vec<_> = (0..items.len())
+                .filter(|n| items[*n].node.kind() == OmlCstKind::Pair)
+                .collect();
+            let mut slotted: Vec<_> = items.into_iter().map(Some).collect();
+            let mut pairs: Vec<_> = slots.iter().filter_map(|n| slotted[*n].take()).collect();
+            pairs.sort_by_key(|item| self.keys(item.node)[0]);
+            for (slot, item) in slots.into_iter().zip(pairs) {
+                slotted[slot] = Some(item);
+            }
+            items = slotted.into_iter().flatten().collect();
+        }
+        for (index, item) in items.into_iter().enumerate() {
+            let mut leading = item.leading;
+            self.inner_comments(item.node, &mut leading);
+            for (blank, comment) in leading {
+                if blank && !sort && !ret.is_empty() {
+                    ret.push("".to_string());
+                }
+                ret.push(format!("{}{}", indent, comment));
+            }
+            if item.blank_before && !sort && index > 0 {
+                ret.push("".to_string());
+            }
+            let mut line = format!("{}{}", indent, self.pair(item.node, indent)?);
+            if let Some(comment) = item.trailing {
+                line = format!("{} {}", line, comment);
+            }
+            ret.push(line);
+        }
+        Ok(())
+    }
+
+    fn block(&self, node: &'a OmlCstNode, trailing: Option<&str>) -> Result<Vec<String>, OmlError> {
+        let mut ret = vec![];
+        let (items, tail) = self.items(node.children(), |node| {
+            !matches!(node.kind(), OmlCstKind::Token | OmlCstKind::Trivia)
+        });
+        let mut head = "".to_string();
+        let mut head_trailing = None;
+        let mut pairs = vec![];
+        for item in items {
+            match item.node.kind() {
+                OmlCstKind::Annotation | OmlCstKind::Head => {
+                    let mut leading = item.leading;
+                    self.inner_comments(item.node, &mut leading);
+                    ret.extend(leading.into_iter().map(|(_, comment)| comment.to_string()));
+                    head_trailing = item.trailing.or(head_trailing);
+                    head.push_str(&match item.node.kind() {
+                        OmlCstKind::Annotation => self.annotation(item.node, "")?,
+                        _ => self.head(item.node)?,
+                    });
+                }
+                _ => pairs.push(item),
+            }
+        }
+        match head_trailing {
+            Some(comment) => ret.push(format!("{} {}", head, comment)),
+            None => ret.push(head),
+        }
+        let mut body = vec![];
+        self.lines(&mut body, pairs, "", self.options.sort_keys)?;
+        ret.extend(body);
+        ret.extend(tail.into_iter().map(|(_, comment)| comment.to_string()));
+        if let (Some(comment), Some(last)) = (trailing, ret.last_mut()) {
+            last.push(' ');
+            last.push_str(comment);
+        }
+        Ok(ret)
+    }
+
+    fn template(
+        &self,
+        node: &'a OmlCstNode,
+        trailing: Option<&str>,
+    ) -> Result<Vec<String>, OmlError> {
+        let is_body =
+            |node: &OmlCstNode| matches!(node.kind(), OmlCstKind::Pair | OmlCstKind::Local);
+        let split = node
+            .children()
+            .iter()
+            .position(is_body)
+            .unwrap_or(node.children().len());
+        let mut ret = vec![];
+        let mut params: Vec<String> = vec![];
+        let mut name = "";
+        for child in node.children()[..split].iter() {
+            match child.kind() {
+                OmlCstKind::Key if name.is_empty() => name = self.text(child),
+                OmlCstKind::Key => params.push(self.text(child).to_string()),
+                OmlCstKind::Value => {
+                    if let Some(param) = params.last_mut() {
+                        *param = format!("{} = {}", param, self.value(child, "")?);
+                    }
+                }
+                _ if self.is_comment(child) => ret.push(self.text(child).trim_end().to_string()),
+                _ => (),
+            }
+        }
+        ret.push(format!("@template {}({}) {{", name, params.join(", ")));
+        let (items, tail) = self.items(&node.children()[split..], is_body);
+        let indent = " ".repeat(self.options.indent);
+        self.lines(&mut ret, items, &indent, self.options.sort_keys)?;
+        ret.extend(
+            tail.into_iter()
+                .map(|(_, comment)| format!("{}{}", indent, comment)),
+        );
+        match trailing {
+            Some(comment) => ret.push(format!("}} {}", comment)),
+            None => ret.push("}".to_string()),
+        }
+        Ok(ret)
+    }
+
+    fn document(&self) -> Result<String, OmlError> {
+        let root = self.doc.root();
+        let (items, tail) = self.items(root.children(), |node| {
+            !matches!(node.kind(), OmlCstKind::Token | OmlCstKind::Trivia)
+        });
+        let mut lines: Vec<String> = vec![];
+        let mut last_kind = None;
+        for item in items {
+            let kind = item.node.kind();
+            // root asserts stay together, groups and templates are spaced out
+            if last_kind.is_some()
+                && (kind != OmlCstKind::Local || last_kind != Some(OmlCstKind::Local))
+            {
+                lines.extend((0..self.options.group_spacing).map(|_| "".to_string()));
+            }
+            last_kind = Some(kind);
+            for (index, (blank, comment)) in item.leading.iter().enumerate() {
+                if *blank && index > 0 {
+                    lines.push("".to_string());
+                }
+                lines.push(comment.to_string());
+            }
+            if item.blank_before && !item.leading.is_empty() {
+                lines.push("".to_string());
+            }
+            match kind {
+                OmlCstKind::Block => lines.extend(self.block(item.node, item.trailing)?),
+                OmlCstKind::Template => lines.extend(self.template(item.node, item.trailing)?),
+                _ => {
+                    let mut ret = vec![];
+                    let item = Item {
+                        leading: vec![],
+                        ..item
+                    };
+                    self.lines(&mut ret, vec![item], "", false)?;
+                    lines.extend(ret);
+                }
+            }
+        }
+        for (blank, comment) in tail.into_iter() {
+            if blank && !lines.is_empty() {
+                lines.push("".to_string());
+            }
+            lines.push(comment.to_string());
+        }
+        let mut ret = lines.join("\n");
+        if !ret.is_empty() {
+            ret.push('\n');
+        }
+        Ok(ret)
+    }
+}
@@ -0,0 +1,217 @@
+use super::eval::FuncEvaluator;
+use super::oml_error::OmlError;
+use super::oml_expr::{OmlExpr, PathAppendExt};
+use super::oml_value::OmlValue;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+const MAX_CALL_DEPTH: usize = 64;
+
+thread_local! {
+    static FUNC_TABLES: RefCell<Vec<(usize, HashMap<String, OmlExpr>)>> = const { RefCell::new(vec![]) };
+    static CALL_STACK: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+/// Makes the `@fn` definitions of a root expression visible while it is being evaluated
+pub(crate) struct FuncTableGuard {
+    installed: bool,
+}
+
+impl FuncTableGuard {
+    pub fn install(root: &OmlExpr) -> Self {
+        let owner = root as *const OmlExpr as usize;
+        FUNC_TABLES.with(|tables| {
+            let mut tables = tables.borrow_mut();
+            if tables.last().map(|(o, _)| *o == owner).unwrap_or(false) {
+                return Self { installed: false };
+            }
+            let mut funcs = HashMap::new();
+            root.collect_funcs("", &mut funcs);
+            tables.push((owner, funcs));
+            Self { installed: true }
+        })
+    }
+}
+
+impl Drop for FuncTableGuard {
+    fn drop(&mut self) {
+        if self.installed {
+            FUNC_TABLES.with(|tables| tables.borrow_mut().pop());
+        }
+    }
+}
+
+struct CallFrame {}
+
+impl CallFrame {
+    fn push(name: &str) -> Result<Self, OmlError> {
+        CALL_STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if stack.len() >= MAX_CALL_DEPTH {
+                return Err(OmlError::CallDepth(name.to_string()));
+            }
+            stack.push(name.to_string());
+            Ok(Self {})
+        })
+    }
+}
+
+impl Drop for CallFrame {
+    fn drop(&mut self) {
+        CALL_STACK.with(|stack| stack.borrow_mut().pop());
+    }
+}
+
+/// Look up a function from the scope of `path` outwards
+fn find_func(path: &str, name: &str) -> Option<(String, OmlExpr)> {
+    FUNC_TABLES.with(|tables| {
+        let tables = tables.borrow();
+        let (_, funcs) = tables.last()?;
+        let mut scope = path.remove_scope();
+        loop {
+            let func_path = scope.append_str(name);
+            if let Some(func) = funcs.get(&func_path) {
+                return Some((func_path, func.clone()));
+            }
+            if scope.is_empty() {
+                return None;
+            }
+            scope = scope.remove_once();
+        }
+    })
+}
+
+impl OmlExpr {
+    fn collect_funcs(&self, path: &str, funcs: &mut HashMap<String, OmlExpr>) {
+        match self {
+            OmlExpr::Map(map) => {
+                for (key, value) in map.iter() {
+                    let new_path = path.append_str(key);
+                    match value {
+                        OmlExpr::LetAnno(func) if matches!(**func, OmlExpr::Lambda(_)) => {
+                            funcs.insert(new_path, (**func).clone());
+                        }
+                        _ => value.collect_funcs(&new_path, funcs),
+                    }
+                }
+            }
+            OmlExpr::Array(arr) => {
+                for (index, item) in arr.iter().enumerate() {
+                    item.collect_funcs(&path.append_num(index), funcs);
+                }
+            }
+            OmlExpr::IfAnno(if_anno) => {
+                for (_, value) in if_anno.exprs.iter() {
+                    value.collect_funcs(path, funcs);
+                }
+                if let Some(value) = &if_anno.default {
+                    value.collect_funcs(path, funcs);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn find_callable(&self, path: &str) -> Option<(String, OmlExpr)> {
+        match self {
+            OmlExpr::Lambda(_) => Some((path.to_string(), self.clone())),
+            OmlExpr::TempName(name) => find_func(path, name),
+            _ => None,
+        }
+    }
+
+    fn call_func(
+        name: &str,
+        func: &OmlExpr,
+        func_path: &str,
+        args: Vec<OmlValue>,
+        eval: &impl Fn(&OmlExpr, &str) -> Result<(OmlValue, bool), OmlError>,
+    ) -> Result<(OmlValue, bool), OmlError> {
+        let OmlExpr::Lambda((params, body)) = func else {
+            return Err(OmlError::IllegalFunction(name.to_string()));
+        };
+        if params.len() != args.len() {
+            return Err(OmlError::Eval(format!(
+                "function {} expects {} arguments.",
+                name,
+                params.len()
+            )));
+        }
+        let body = (params.iter().zip(args.iter())).fold((**body).clone(), |body, (param, arg)| {
+            body.bind_var(param, arg)
+        });
+        let _frame = CallFrame::push(name)?;
+        eval(&body, func_path)
+    }
+
+    pub(crate) fn invoke_with(
+        obj: &OmlExpr,
+        name: &str,
+        args: &[OmlExpr],
+        path: &str,
+        eval: &impl Fn(&OmlExpr, &str) -> Result<(OmlValue, bool), OmlError>,
+    ) -> Result<(OmlValue, bool), OmlError> {
+        let receiver = match obj {
+            OmlExpr::None => None,
+            _ => Some(obj),
+        };
+        let func = match receiver {
+            Some(_) => None,
+            None => find_func(path, name),
+        };
+        let higher_order = receiver.is_some() && matches!(name, "map" | "filter" | "reduce");
+        let mut args1 = vec![];
+        for (index, arg) in receiver.into_iter().chain(args.iter()).enumerate() {
+            // the function argument of a collection method is called instead of evaluated
+            if higher_order && index == 1 {
+                args1.push(OmlValue::None);
+                continue;
+            }
+            match eval(arg, path)? {
+                (val, true) => args1.push(val),
+                (_, false) => return Ok((OmlValue::None, false)),
+            }
+        }
+        if let Some((func_path, func)) = func {
+            return Self::call_func(name, &func, &func_path, args1, eval);
+        }
+        if !higher_order {
+            return Ok((FuncEvaluator::invoke(name, args1)?, true));
+        }
+        let mut args1 = args1.into_iter();
+        let items = match args1.next() {
+            Some(OmlValue::Array(items)) => items,
+            Some(OmlValue::None) => return Err(OmlError::NoneOperand(name.to_string())),
+            _ => return Err(OmlError::IllegalFunction(name.to_string())),
+        };
+        let (func_path, func) = match args.first().and_then(|arg| arg.find_callable(path)) {
+            Some(func) => func,
+            None => return Err(OmlError::IllegalFunction(name.to_string())),
+        };
+        let call = |args: Vec<OmlValue>| Self::call_func(name, &func, &func_path, args, eval);
+        let mut ret = vec![];
+        let mut acc = args1.nth(1).unwrap_or(OmlValue::None);
+        for item in items.into_iter() {
+            let (val, tmp_success) = match name {
+                "reduce" => call(vec![acc.clone(), item.clone()])?,
+                _ => call(vec![item.clone()])?,
+            };
+            if !tmp_success {
+                return Ok((OmlValue::None, false));
+            }
+            match (name, val) {
+                ("map", val) => ret.push(val),
+                ("filter", OmlValue::Bool(true)) => ret.push(item),
+                ("filter", OmlValue::Bool(false)) => (),
+                ("filter", _) => {
+                    return Err(OmlError::Eval("condition must be boolean.".to_string()))
+                }
+                (_, val) => acc = val,
+            }
+        }
+        Ok(match name {
+            "reduce" => (acc, true),
+            _ => (OmlValue::Array(ret), true),
+        })
+    }
+}
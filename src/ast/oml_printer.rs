@@ -0,0 +1,615 @@
+use super::join_path;
+use super::oml_error::OmlError;
+use super::oml_expr::{get_op2_level, OmlExpr, OmlExprIfAnno, OmlMatchPattern};
+use super::oml_value::OmlValue;
+use indexmap::IndexMap;
+
+pub(crate) fn is_id(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn is_ids(s: &str) -> bool {
+    s.split('.').all(is_id)
+}
+
+fn key_error(path: &str, key: &str) -> OmlError {
+    let msg = format!("key \"{}\" isn't a valid oml identifier", key);
+    OmlError::Serialize(match path.is_empty() {
+        true => msg,
+        false => format!("{}: {}", path, msg),
+    })
+}
+
+/// Template parameters are stored as `@name` locals, write them under a name the parser accepts
+fn local_name(name: &str) -> String {
+    name.replace('@', "__")
+}
+
+/// String literals are taken verbatim, a quote can only be written as part of `\"`
+///
+/// `closing` is set when the text is followed by the closing quote.
+fn write_string_text(s: &str, closing: bool) -> Result<String, OmlError> {
+    if !oml_syntax::is_literal_text(s, closing) {
+        let msg = format!("string {:?} can't be written as an oml literal", s);
+        return Err(OmlError::Serialize(msg));
+    }
+    Ok(s.to_string())
+}
+
+fn write_string(s: &str) -> Result<String, OmlError> {
+    Ok(format!("\"{}\"", write_string_text(s, true)?))
+}
+
+fn write_float(f: f64) -> String {
+    match f {
+        f if f.is_nan() => "(0.0 / 0.0)".to_string(),
+        f if f.is_infinite() && f > 0.0 => "(1.0 / 0.0)".to_string(),
+        f if f.is_infinite() => "(-1.0 / 0.0)".to_string(),
+        f => {
+            let s = f.to_string();
+            match s.contains('.') {
+                true => s,
+                false => format!("{}.0", s),
+            }
+        }
+    }
+}
+
+fn write_value(value: &OmlValue) -> Result<String, OmlError> {
+    Ok(match value {
+        OmlValue::None => "null".to_string(),
+        OmlValue::Bool(b) => b.to_string(),
+        OmlValue::Int64(n) => n.to_string(),
+        OmlValue::Float64(f) => write_float(*f),
+        OmlValue::String(s) => write_string(s)?,
+        OmlValue::Array(arr) => {
+            let items: Result<Vec<_>, _> = arr.iter().map(write_value).collect();
+            format!("[{}]", items?.join(", "))
+        }
+        OmlValue::Map(map) => {
+            let mut items = vec![];
+            for (key, item) in map.iter() {
+                if !is_ids(key) {
+                    return Err(key_error("", key));
+                }
+                items.push(format!("{}: {}", key, write_value(item)?));
+            }
+            write_braces(items)
+        }
+    })
+}
+
+fn write_braces(items: Vec<String>) -> String {
+    match items.is_empty() {
+        true => "{}".to_string(),
+        false => format!("{{ {} }}", items.join(", ")),
+    }
+}
+
+/// Expressions that extend as far right as possible and need parentheses as operands
+fn is_open(expr: &OmlExpr) -> bool {
+    matches!(
+        expr,
+        OmlExpr::Op3(_) | OmlExpr::Let(_) | OmlExpr::Lambda(_) | OmlExpr::IfAnno(_)
+    )
+}
+
+fn is_negative(expr: &OmlExpr) -> bool {
+    match expr {
+        OmlExpr::Value(OmlValue::Int64(n)) => *n < 0,
+        OmlExpr::Value(OmlValue::Float64(f)) => f.is_sign_negative() || !f.is_finite(),
+        _ => false,
+    }
+}
+
+/// `let` values stop at the first `in`, so operators using it must be wrapped
+fn has_bare_in(expr: &OmlExpr) -> bool {
+    match expr {
+        OmlExpr::Op2((left, op, right)) => {
+            op == "in" || op == "not in" || has_bare_in(left) || has_bare_in(right)
+        }
+        _ => false,
+    }
+}
+
+fn wrap(expr: &OmlExpr, need: bool) -> Result<String, OmlError> {
+    let ret = write_expr(expr)?;
+    Ok(match need {
+        true => format!("({})", ret),
+        false => ret,
+    })
+}
+
+fn write_exprs(exprs: &[OmlExpr]) -> Result<String, OmlError> {
+    let items: Result<Vec<_>, _> = exprs.iter().map(write_expr).collect();
+    Ok(items?.join(", "))
+}
+
+fn as_compare(expr: &OmlExpr) -> Option<(&OmlExpr, &str, &OmlExpr)> {
+    match expr {
+        OmlExpr::Op2((left, op, right)) if get_op2_level(op) == get_op2_level("<") => {
+            Some((left, op, right))
+        }
+        _ => None,
+    }
+}
+
+fn write_compare_operand(expr: &OmlExpr) -> Result<String, OmlError> {
+    let need = match expr {
+        OmlExpr::Op2((_, op, _)) => get_op2_level(op) >= get_op2_level("<"),
+        expr => is_open(expr),
+    };
+    wrap(expr, need)
+}
+
+/// `a < b && b < c` as parsed from `a < b < c`, with the text of the last operand
+fn write_chain(expr: &OmlExpr) -> Result<Option<(String, String)>, OmlError> {
+    if let OmlExpr::Op2((left, op, right)) = expr {
+        if op == "&&" {
+            let (chain, last) = match write_chain(left)? {
+                Some(chain) => chain,
+                None => return Ok(None),
+            };
+            return Ok(match as_compare(right) {
+                Some((left, op, right)) if write_compare_operand(left)? == last => {
+                    let right = write_compare_operand(right)?;
+                    Some((format!("{} {} {}", chain, op, right), right))
+                }
+                _ => None,
+            });
+        }
+    }
+    Ok(match as_compare(expr) {
+        Some((left, op, right)) => {
+            let right = write_compare_operand(right)?;
+            let left = write_compare_operand(left)?;
+            Some((format!("{} {} {}", left, op, right), right))
+        }
+        None => None,
+    })
+}
+
+/// Operand of `.name`, `.func()` and suffix operators
+fn write_suffix_operand(expr: &OmlExpr, access: bool) -> Result<String, OmlError> {
+    let need = is_open(expr)
+        || is_negative(expr)
+        || matches!(expr, OmlExpr::Op1Prefix(_) | OmlExpr::Op2(_))
+        // `a.b` would be read back as a single name
+        || (access && matches!(expr, OmlExpr::TempName(_)));
+    wrap(expr, need)
+}
+
+fn write_map_items(map: &IndexMap<String, OmlExpr>) -> Result<Vec<String>, OmlError> {
+    let mut items = vec![];
+    for (key, item) in map.iter() {
+        if !is_ids(key) {
+            return Err(key_error("", key));
+        }
+        items.push(format!("{}: {}", key, write_expr(item)?));
+    }
+    Ok(items)
+}
+
+fn write_if_anno(if_anno: &OmlExprIfAnno) -> Result<String, OmlError> {
+    // an annotation inside an expression can only be written as the equivalent ternary
+    let mut ret = match &if_anno.default {
+        Some(default) => write_expr(default)?,
+        None => "null".to_string(),
+    };
+    for (cond, value) in if_anno.exprs.iter().rev() {
+        ret = format!(
+            "{} ? {} : {}",
+            wrap(cond, is_open(cond))?,
+            wrap(value, is_open(value))?,
+            ret
+        );
+    }
+    Ok(ret)
+}
+
+fn write_pattern(pattern: &OmlMatchPattern) -> Result<String, OmlError> {
+    Ok(match pattern {
+        OmlMatchPattern::Value(value) => write_value(value)?,
+        OmlMatchPattern::Range((start, end, inclusive)) => {
+            let bound = |value: &Option<OmlValue>| match value {
+                Some(value) => write_value(value),
+                None => Ok("".to_string()),
+            };
+            let op = if *inclusive { "..=" } else { ".." };
+            format!("{}{}{}", bound(start)?, op, bound(end)?)
+        }
+        OmlMatchPattern::Wildcard => "_".to_string(),
+    })
+}
+
+fn write_lambda_args(args: &[String]) -> String {
+    match args.len() {
+        1 => args[0].clone(),
+        _ => format!("({})", args.join(", ")),
+    }
+}
+
+pub(crate) fn write_expr(expr: &OmlExpr) -> Result<String, OmlError> {
+    Ok(match expr {
+        OmlExpr::None => "null".to_string(),
+        OmlExpr::Value(value) => write_value(value)?,
+        OmlExpr::EnvString(s) => write_string(s)?,
+        OmlExpr::Array(arr) => format!("[{}]", write_exprs(arr)?),
+        OmlExpr::Map(map) => write_braces(write_map_items(map)?),
+        OmlExpr::TempName(name) => local_name(name),
+        OmlExpr::Op1Prefix((op, expr)) => {
+            let need = is_open(expr)
+                || is_negative(expr)
+                || matches!(**expr, OmlExpr::Op1Prefix(_) | OmlExpr::Op2(_));
+            format!("{}{}", op, wrap(expr, need)?)
+        }
+        OmlExpr::Op1Suffix((expr, op)) => format!("{}{}", write_suffix_operand(expr, false)?, op),
+        OmlExpr::Op2((left, op, right)) => {
+            if let Some((chain, _)) = write_chain(expr)? {
+                return Ok(chain);
+            }
+            let level = get_op2_level(op);
+            let cmp_level = get_op2_level("<");
+            // operators of one level are left associative, comparisons would turn into a chain
+            let need = |expr: &OmlExpr, right: bool| match expr {
+                OmlExpr::Op2((_, op, _)) => {
+                    let level2 = get_op2_level(op);
+                    level2 > level || (level2 == level && (right || level == cmp_level))
+                }
+                expr => is_open(expr),
+            };
+            format!(
+                "{} {} {}",
+                wrap(left, need(left, false))?,
+                op,
+                wrap(right, need(right, true))?
+            )
+        }
+        OmlExpr::Op3((cond, left, right)) => format!(
+            "{} ? {} : {}",
+            wrap(cond, is_open(cond))?,
+            wrap(left, is_open(left))?,
+            write_expr(right)?
+        ),
+        OmlExpr::FormatString((strs, exprs)) => {
+            let escape = |index: usize| -> Result<String, OmlError> {
+                let s = strs.get(index).map(|s| &s[..]).unwrap_or("");
+                if s.contains(['\n', '\r']) {
+                    let msg = format!("format string part {:?} can't hold a line break", s);
+                    return Err(OmlError::Serialize(msg));
+                }
+                let s = s.replace('{', "{{").replace('}', "}}");
+                write_string_text(&s, index == exprs.len())
+            };
+            let mut ret = format!("$\"{}", escape(0)?);
+            for (index, (expr, spec)) in exprs.iter().enumerate() {
+                // a leading `{` would read as an escaped brace
+                let need = is_open(expr)
+                    || matches!(expr, OmlExpr::Map(_) | OmlExpr::SpreadMap(_))
+                    || matches!(expr, OmlExpr::Comprehension(comp) if comp.key.is_some());
+                ret.push('{');
+                ret.push_str(&wrap(expr, need)?);
+                if let Some(spec) = spec {
+                    ret.push_str(&spec.to_string());
+                }
+                ret.push('}');
+                ret.push_str(&escape(index + 1)?);
+            }
+            ret.push('"');
+            ret
+        }
+        OmlExpr::AccessVar((expr, name)) => {
+            format!("{}.{}", write_suffix_operand(expr, true)?, name)
+        }
+        OmlExpr::InvokeFunc((obj, name, args)) => match **obj {
+            OmlExpr::None => format!("{}({})", name, write_exprs(args)?),
+            _ => format!(
+                "{}.{}({})",
+                write_suffix_operand(obj, false)?,
+                name,
+                write_exprs(args)?
+            ),
+        },
+        OmlExpr::IfAnno(if_anno) => write_if_anno(if_anno)?,
+        OmlExpr::Comprehension(comp) => {
+            let mut ret = write_expr(&comp.value)?;
+            if let Some(key) = &comp.key {
+                ret = format!("{}: {}", write_expr(key)?, ret);
+            }
+            ret = format!(
+                "{} for {} in {}",
+                ret,
+                comp.names.join(", "),
+                write_expr(&comp.source)?
+            );
+            if let Some(cond) = &comp.cond {
+                ret = format!("{} if {}", ret, write_expr(cond)?);
+            }
+            match comp.key.is_some() {
+                true => format!("{{ {} }}", ret),
+                false => format!("[{}]", ret),
+            }
+        }
+        OmlExpr::Spread(expr) => format!("...{}", write_expr(expr)?),
+        OmlExpr::SpreadMap(parts) => {
+            let mut items = vec![];
+            for part in parts.iter() {
+                match part {
+                    OmlExpr::Map(map) => items.extend(write_map_items(map)?),
+                    part => items.push(write_expr(part)?),
+                }
+            }
+            write_braces(items)
+        }
+        OmlExpr::Let(_) => {
+            // nested lets are written back as one binding list
+            let mut bindings = vec![];
+            let mut expr = expr;
+            while let OmlExpr::Let((name, value, body)) = expr {
+                let value = wrap(value, is_open(value) || has_bare_in(value))?;
+                bindings.push(format!("{} = {}", name, value));
+                expr = body;
+            }
+            format!("let {} in {}", bindings.join(", "), write_expr(expr)?)
+        }
+        OmlExpr::LetAnno(expr) => write_expr(expr)?,
+        OmlExpr::AssertAnno((cond, _)) => write_expr(cond)?,
+        OmlExpr::Lambda((args, body)) => {
+            format!("{} => {}", write_lambda_args(args), write_expr(body)?)
+        }
+        OmlExpr::Match(match_expr) => {
+            let mut arms = vec![];
+            for (patterns, expr) in match_expr.arms.iter() {
+                let patterns: Result<Vec<_>, _> = patterns.iter().map(write_pattern).collect();
+                arms.push(format!(
+                    "{} => {}",
+                    patterns?.join(" | "),
+                    write_expr(expr)?
+                ));
+            }
+            format!(
+                "match {} {{ {} }}",
+                write_expr(&match_expr.value)?,
+                arms.join(", ")
+            )
+        }
+    })
+}
+
+fn is_group_map(map: &IndexMap<String, OmlExpr>) -> bool {
+    map.keys().all(|key| is_id(key) || key.starts_with('@'))
+}
+
+/// Maps with dotted keys stay expressions, anything else is written as a group so bad keys get a path
+fn is_group_or_invalid(map: &IndexMap<String, OmlExpr>) -> bool {
+    is_group_map(map) || !map.keys().all(|key| is_ids(key))
+}
+
+fn is_group_array(arr: &[OmlExpr]) -> bool {
+    !arr.is_empty()
+        && (arr.iter()).all(|item| matches!(item, OmlExpr::Map(map) if is_group_map(map)))
+}
+
+fn start_block(ret: &mut String) {
+    if !ret.is_empty() {
+        ret.push('\n');
+    }
+}
+
+/// One line of a group body, `prefix` is set for dotted keys of nested maps
+fn write_pair(
+    ret: &mut String,
+    path: &str,
+    prefix: &str,
+    key: &str,
+    item: &OmlExpr,
+) -> Result<(), OmlError> {
+    let is_local = matches!(item, OmlExpr::LetAnno(_) | OmlExpr::AssertAnno(_));
+    if (is_local && !prefix.is_empty()) || (!is_local && !is_id(key)) {
+        return Err(key_error(path, key));
+    }
+    match item {
+        OmlExpr::LetAnno(expr) => match &**expr {
+            OmlExpr::Lambda((args, body)) => ret.push_str(&format!(
+                "@fn {} {} = {}\n",
+                local_name(key),
+                write_lambda_args(args),
+                write_expr(body)?
+            )),
+            expr => ret.push_str(&format!(
+                "@let {} = {}\n",
+                local_name(key),
+                write_expr(expr)?
+            )),
+        },
+        OmlExpr::AssertAnno((cond, message)) => ret.push_str(&format!(
+            "@assert {}, {}\n",
+            write_expr(cond)?,
+            write_expr(message)?
+        )),
+        OmlExpr::IfAnno(if_anno) => {
+            for (cond, value) in if_anno.exprs.iter() {
+                let value = write_expr(value)?;
+                ret.push_str(&format!(
+                    "@if {} {}{} = {}\n",
+                    write_expr(cond)?,
+                    prefix,
+                    key,
+                    value
+                ));
+            }
+            // the plain assignment has to come last to end up as the default
+            if let Some(default) = &if_anno.default {
+                write_pair(ret, path, prefix, key, default)?;
+            }
+        }
+        item => ret.push_str(&format!("{}{} = {}\n", prefix, key, write_expr(item)?)),
+    }
+    Ok(())
+}
+
+/// Body of a block that can't hold sub groups, nested maps become dotted keys
+fn write_flat(
+    ret: &mut String,
+    path: &str,
+    prefix: &str,
+    map: &IndexMap<String, OmlExpr>,
+) -> Result<(), OmlError> {
+    for (key, item) in map.iter() {
+        match item {
+            OmlExpr::Map(map1) if !map1.is_empty() && is_id(key) && is_group_map(map1) => {
+                write_flat(ret, path, &format!("{}{}.", prefix, key), map1)?
+            }
+            item => write_pair(ret, path, prefix, key, item)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_group(
+    ret: &mut String,
+    path: &str,
+    map: &IndexMap<String, OmlExpr>,
+) -> Result<(), OmlError> {
+    let mut body = "".to_string();
+    let mut nested = vec![];
+    for (key, item) in map.iter() {
+        match item {
+            OmlExpr::Map(map1) if is_id(key) && is_group_or_invalid(map1) => {
+                nested.push((key, item))
+            }
+            OmlExpr::Array(arr) if is_id(key) && is_group_array(arr) => nested.push((key, item)),
+            item => write_pair(&mut body, path, "", key, item)?,
+        }
+    }
+    // a header without body is only needed to create an empty group
+    if !body.is_empty() || nested.is_empty() {
+        start_block(ret);
+        ret.push_str(&format!("[{}]\n{}", path, body));
+    }
+    for (key, item) in nested {
+        let path = join_path(path, key);
+        match item {
+            OmlExpr::Map(map) => write_group(ret, &path, map)?,
+            OmlExpr::Array(arr) => {
+                for item in arr.iter() {
+                    if let OmlExpr::Map(map) = item {
+                        start_block(ret);
+                        ret.push_str(&format!("[[{}]]\n", path));
+                        write_flat(ret, &path, "", map)?;
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+/// `@if` blocks keep the whole group under one annotation
+fn write_if_block(ret: &mut String, cond: &OmlExpr, value: &OmlExpr) -> Result<(), OmlError> {
+    let mut head = vec![];
+    let mut value = value;
+    while let OmlExpr::Map(map) = value {
+        match map.first() {
+            Some((key, item))
+                if map.len() == 1 && is_id(key) && matches!(item, OmlExpr::Map(_)) =>
+            {
+                head.push(&key[..]);
+                value = item;
+            }
+            _ => break,
+        }
+    }
+    let (head, is_array) = match value {
+        OmlExpr::Map(map) if map.len() == 1 => match map.first() {
+            Some((key, OmlExpr::Array(arr))) if arr.len() == 1 && is_group_array(arr) => {
+                head.push(&key[..]);
+                value = &arr[0];
+                (head.join("."), true)
+            }
+            _ => (head.join("."), false),
+        },
+        _ => (head.join("."), false),
+    };
+    let map = match value {
+        OmlExpr::Map(map) if !head.is_empty() => map,
+        _ => {
+            let msg = "@if block must hold a single group".to_string();
+            return Err(OmlError::Serialize(msg));
+        }
+    };
+    start_block(ret);
+    let cond = write_expr(cond)?;
+    match is_array {
+        true => ret.push_str(&format!("@if {} [[{}]]\n", cond, head)),
+        false => ret.push_str(&format!("@if {} [{}]\n", cond, head)),
+    }
+    write_flat(ret, &head, "", map)
+}
+
+fn write_document(ret: &mut String, expr: &OmlExpr) -> Result<(), OmlError> {
+    match expr {
+        OmlExpr::None => Ok(()),
+        OmlExpr::Map(map) => {
+            // root asserts must come before the first group
+            for (key, item) in map.iter() {
+                if let OmlExpr::AssertAnno(_) = item {
+                    write_pair(ret, "", "", key, item)?;
+                }
+            }
+            for (key, item) in map.iter() {
+                if !is_id(key) && !key.starts_with('@') {
+                    return Err(key_error("", key));
+                }
+                match item {
+                    OmlExpr::AssertAnno(_) => (),
+                    OmlExpr::Map(map) if is_group_or_invalid(map) => write_group(ret, key, map)?,
+                    OmlExpr::Array(arr) if is_group_array(arr) => {
+                        let map = [(key.clone(), item.clone())].into_iter().collect();
+                        write_group(ret, "", &map)?;
+                    }
+                    _ => {
+                        let msg = format!("{}: only groups can be written at the root", key);
+                        return Err(OmlError::Serialize(msg));
+                    }
+                }
+            }
+            Ok(())
+        }
+        OmlExpr::IfAnno(if_anno) => {
+            for (cond, value) in if_anno.exprs.iter() {
+                write_if_block(ret, cond, value)?;
+            }
+            match &if_anno.default {
+                Some(default) => write_document(ret, default),
+                None => Ok(()),
+            }
+        }
+        _ => Err(OmlError::Serialize(
+            "oml document must be a map of groups".to_string(),
+        )),
+    }
+}
+
+impl OmlExpr {
+    /// Write the tree back as `.oml` source, `from_str` reads the result into an equivalent tree
+    pub fn to_oml_string(&self) -> Result<String, OmlError> {
+        let mut ret = "".to_string();
+        write_document(&mut ret, self)?;
+        Ok(ret)
+    }
+}
+
+impl OmlValue {
+    /// Write an evaluated map of groups as `.oml` source
+    pub fn to_oml_string(&self) -> Result<String, OmlError> {
+        OmlExpr::from(self.clone()).to_oml_string()
+    }
+}
@@ -1,7 +1,7 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::ops::{Index, IndexMut};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum OmlValue {
     None,
     Bool(bool),
@@ -9,7 +9,7 @@ pub enum OmlValue {
     Float64(f64),
     String(String),
     Array(Vec<OmlValue>),
-    Map(HashMap<String, OmlValue>),
+    Map(IndexMap<String, OmlValue>),
 }
 
 impl OmlValue {
@@ -74,17 +74,10 @@ impl OmlValue {
                 format!("[{}]", arr.join(", "))
             }
             OmlValue::Map(map) => {
-                let mut ret = "{ ".to_string();
-                for (key, value) in map.iter() {
-                    if !ret.is_empty() {
-                        ret.push_str(", ");
-                    }
-                    ret.push_str(key);
-                    ret.push_str(": ");
-                    ret.push_str(&value.as_str());
-                }
-                ret.push_str(" }");
-                ret
+                let map: Vec<_> = (map.iter())
+                    .map(|(key, value)| format!("{}: {}", key, value.as_str()))
+                    .collect();
+                format!("{{ {} }}", map.join(", "))
             }
         }
     }
@@ -117,7 +110,7 @@ impl OmlValue {
         }
     }
 
-    pub fn as_map(&self) -> Option<HashMap<String, OmlValue>> {
+    pub fn as_map(&self) -> Option<IndexMap<String, OmlValue>> {
         match self {
             OmlValue::Map(map) => Some(map.clone()),
             _ => None,
@@ -192,7 +185,7 @@ impl IndexMut<&str> for OmlValue {
             return self;
         } else {
             if !self.is_map() {
-                *self = OmlValue::Map(HashMap::new());
+                *self = OmlValue::Map(IndexMap::new());
             }
             if let OmlValue::Map(map) = self {
                 if map.get(index).is_none() {
@@ -317,7 +310,7 @@ pub trait ApplyExt {
     fn apply(&mut self, val: Self);
 }
 
-impl ApplyExt for HashMap<String, OmlValue> {
+impl ApplyExt for IndexMap<String, OmlValue> {
     fn apply(&mut self, val: Self) {
         for (key, mut val) in val.into_iter() {
             if let Some(self_k) = self.get_mut(&key) {
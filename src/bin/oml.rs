@@ -0,0 +1,326 @@
+use oml::{FormatOptions, OmlDocument, OmlError, OmlExpr, OmlValue};
+use std::io::Read;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: oml <command> [args]
+
+commands:
+  eval <file> [--path a.b] [--format json|oml]   print the evaluated value
+  check <file>...                               report parse and evaluation errors
+  fmt [--check] <file>...                       format files in place
+  convert <file> --to json|toml|yaml|oml        convert between formats
+  get <path> [file]                             print a single value
+
+`-` or a missing file reads stdin, .json and .toml files are imported by extension.";
+
+/// Failure of a command, usage errors exit with 2
+enum CliError {
+    Usage(String),
+    Failed(String),
+}
+
+impl From<OmlError> for CliError {
+    fn from(err: OmlError) -> Self {
+        CliError::Failed(err.to_string())
+    }
+}
+
+type CliResult<T> = Result<T, CliError>;
+
+fn read_input(file: &str) -> CliResult<String> {
+    let mut content = String::new();
+    let ret = match file {
+        "-" => std::io::stdin()
+            .read_to_string(&mut content)
+            .map(|_| content),
+        file => std::fs::read_to_string(file),
+    };
+    ret.map_err(|err| CliError::Failed(format!("{}: {}", file, err)))
+}
+
+/// Prefix errors with `file:line:col` when the parser reported a position
+fn locate(file: &str, err: &OmlError) -> String {
+    let msg = err.to_string();
+    let pos = msg.split_once("--> ").and_then(|(_, rest)| {
+        let pos = rest.split_whitespace().next()?;
+        let (line, col) = pos.split_once(':')?;
+        Some((line.parse::<usize>().ok()?, col.parse::<usize>().ok()?))
+    });
+    match pos {
+        Some((line, col)) => {
+            // pest renders a source excerpt, the reason is on its last `= ...` line
+            let reason = (msg.lines().rev())
+                .find_map(|line| line.trim_start().strip_prefix("= "))
+                .unwrap_or(&msg);
+            format!("{}:{}:{}: {}", file, line, col, reason)
+        }
+        None => format!("{}: {}", file, msg),
+    }
+}
+
+fn load(file: &str, content: &str) -> CliResult<OmlExpr> {
+    let ret = if file.ends_with(".json") {
+        OmlExpr::from_json(content)
+    } else if file.ends_with(".toml") {
+        OmlExpr::from_toml(content).map(|(expr, warnings)| {
+            for (path, msg) in warnings {
+                eprintln!("{}: {}: {}", file, path, msg);
+            }
+            expr
+        })
+    } else {
+        OmlExpr::from_str(content)
+    };
+    ret.map_err(|err| CliError::Failed(locate(file, &err)))
+}
+
+fn evalute(file: &str) -> CliResult<OmlValue> {
+    let content = read_input(file)?;
+    let err = match load(file, &content)?.evalute() {
+        Ok(value) => return Ok(value),
+        Err(err) => err,
+    };
+    match OmlDocument::parse(&content) {
+        Ok(doc) if !file.ends_with(".json") && !file.ends_with(".toml") => {
+            Err(CliError::Failed(describe(file, &doc, err)))
+        }
+        _ => Err(CliError::Failed(format!("{}: {}", file, err))),
+    }
+}
+
+/// Prefix evaluation errors with the `file:line:col` of the value or assert that failed
+fn describe(file: &str, doc: &OmlDocument, err: OmlError) -> String {
+    let line_col = |offset: usize| {
+        let (line, col) = doc.line_col(offset);
+        format!("{}:{}:{}", file, line, col)
+    };
+    // values built by a comprehension or a spread have no range of their own, their
+    // nearest written ancestor is used instead
+    let position = |path: &str| {
+        let mut path = path;
+        loop {
+            if let Some(range) = doc.range_at(path) {
+                return line_col(range.start);
+            }
+            match path.rfind('.') {
+                Some(pos) => path = &path[..pos],
+                None => return file.to_string(),
+            }
+        }
+    };
+    match err {
+        OmlError::Assert(failures) => {
+            let lines: Vec<_> = (failures.iter())
+                .map(|(_, offset, msg)| format!("{}: assertion failed: {}", line_col(*offset), msg))
+                .collect();
+            lines.join("\n")
+        }
+        OmlError::Deserialize((path, msg)) => format!("{}: {}", position(&path), msg),
+        OmlError::At((path, err)) => format!("{}: {}", position(&path), err),
+        err => format!("{}: {}", file, err),
+    }
+}
+
+fn select<'a>(value: &'a OmlValue, path: &str) -> CliResult<&'a OmlValue> {
+    match path.is_empty() {
+        true => Ok(value),
+        false => (value.get_with_path(path))
+            .ok_or_else(|| CliError::Failed(format!("{}: no such key", path))),
+    }
+}
+
+fn write_as(value: &OmlValue, format: &str) -> CliResult<String> {
+    let mut ret = match format {
+        "json" => value.to_json_string_pretty()?,
+        "toml" => value.to_toml_string()?,
+        "yaml" => value.to_yaml_string(),
+        "oml" => value.to_oml_string()?,
+        format => return Err(CliError::Usage(format!("unknown format: {}", format))),
+    };
+    if !ret.ends_with('\n') {
+        ret.push('\n');
+    }
+    Ok(ret)
+}
+
+/// Positional arguments and `--name value` options, `flags` take no value
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String], options: &[&str], flags: &[&str]) -> CliResult<Self> {
+        let mut ret = Args {
+            positional: vec![],
+            options: vec![],
+            flags: vec![],
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if options.contains(&name) => {
+                    let value = (iter.next())
+                        .ok_or_else(|| CliError::Usage(format!("--{} needs a value", name)))?;
+                    ret.options.push((name.to_string(), value.clone()));
+                }
+                Some(name) if flags.contains(&name) => ret.flags.push(name.to_string()),
+                Some(name) => return Err(CliError::Usage(format!("unknown option: --{}", name))),
+                None => ret.positional.push(arg.clone()),
+            }
+        }
+        Ok(ret)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        (self.options.iter().rev())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| &value[..])
+    }
+
+    fn file(&self, index: usize) -> &str {
+        self.positional.get(index).map(|s| &s[..]).unwrap_or("-")
+    }
+}
+
+fn cmd_eval(args: &[String]) -> CliResult<()> {
+    let args = Args::parse(args, &["path", "format"], &[])?;
+    let value = evalute(args.file(0))?;
+    let value = select(&value, args.option("path").unwrap_or(""))?;
+    print!(
+        "{}",
+        write_as(value, args.option("format").unwrap_or("json"))?
+    );
+    Ok(())
+}
+
+fn check_file(file: &str) -> CliResult<()> {
+    let content = read_input(file)?;
+    let doc = OmlDocument::parse(&content).map_err(|err| CliError::Failed(locate(file, &err)))?;
+    match doc.to_expr()?.evalute() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(CliError::Failed(describe(file, &doc, err))),
+    }
+}
+
+fn cmd_check(args: &[String]) -> CliResult<()> {
+    let args = Args::parse(args, &[], &[])?;
+    let files = match args.positional.is_empty() {
+        true => vec!["-".to_string()],
+        false => args.positional,
+    };
+    let mut failed = 0;
+    for file in files.iter() {
+        match check_file(file) {
+            Ok(()) => (),
+            Err(CliError::Failed(msg) | CliError::Usage(msg)) => {
+                eprintln!("{}", msg);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(CliError::Failed(format!(
+            "{} of {} files failed",
+            n,
+            files.len()
+        ))),
+    }
+}
+
+fn cmd_fmt(args: &[String]) -> CliResult<()> {
+    let args = Args::parse(args, &["indent"], &["check", "sort-keys"])?;
+    let mut options = FormatOptions {
+        sort_keys: args.flags.iter().any(|flag| flag == "sort-keys"),
+        ..Default::default()
+    };
+    if let Some(indent) = args.option("indent") {
+        options.indent =
+            (indent.parse()).map_err(|_| CliError::Usage(format!("invalid indent: {}", indent)))?;
+    }
+    let check = args.flags.iter().any(|flag| flag == "check");
+    if args.positional.is_empty() {
+        let content = read_input("-")?;
+        print!(
+            "{}",
+            oml::format(&content, options.clone())
+                .map_err(|err| CliError::Failed(locate("-", &err)))?
+        );
+        return Ok(());
+    }
+    let mut changed = vec![];
+    for file in args.positional.iter() {
+        let content = read_input(file)?;
+        let formatted = (oml::format(&content, options.clone()))
+            .map_err(|err| CliError::Failed(locate(file, &err)))?;
+        if formatted == content {
+            continue;
+        }
+        match check {
+            true => changed.push(file.clone()),
+            false => std::fs::write(file, formatted)
+                .map_err(|err| CliError::Failed(format!("{}: {}", file, err)))?,
+        }
+    }
+    match changed.is_empty() {
+        true => Ok(()),
+        false => Err(CliError::Failed(format!(
+            "not formatted: {}",
+            changed.join(", ")
+        ))),
+    }
+}
+
+fn cmd_convert(args: &[String]) -> CliResult<()> {
+    let args = Args::parse(args, &["to"], &[])?;
+    let to = args
+        .option("to")
+        .ok_or_else(|| CliError::Usage("convert needs --to json|toml|yaml|oml".to_string()))?;
+    let value = evalute(args.file(0))?;
+    print!("{}", write_as(&value, to)?);
+    Ok(())
+}
+
+fn cmd_get(args: &[String]) -> CliResult<()> {
+    let args = Args::parse(args, &[], &[])?;
+    let path =
+        (args.positional.first()).ok_or_else(|| CliError::Usage("get needs a path".to_string()))?;
+    let value = evalute(args.file(1))?;
+    // scalars are printed bare so shell scripts can use them directly
+    match select(&value, path)? {
+        OmlValue::None => println!("null"),
+        value @ (OmlValue::Array(_) | OmlValue::Map(_)) => println!("{}", value.to_json_string()?),
+        value => println!("{}", value.as_str()),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let ret = match args.first().map(|s| &s[..]) {
+        Some("eval") => cmd_eval(&args[1..]),
+        Some("check") => cmd_check(&args[1..]),
+        Some("fmt") => cmd_fmt(&args[1..]),
+        Some("convert") => cmd_convert(&args[1..]),
+        Some("get") => cmd_get(&args[1..]),
+        Some("-h" | "--help" | "help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(cmd) => Err(CliError::Usage(format!("unknown command: {}", cmd))),
+        None => Err(CliError::Usage("missing command".to_string())),
+    };
+    match ret {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Failed(msg)) => {
+            eprintln!("{}", msg);
+            ExitCode::from(1)
+        }
+        Err(CliError::Usage(msg)) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::ast::join_path;
+use crate::ast::oml_error::OmlError;
+use crate::overrides::env_overrides;
+use crate::{IndexMap, OmlExpr, OmlValue};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Where a value of an `OmlBuilder` came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum OmlSource {
+    /// Source text added with `add_str`, under the name it was given
+    Embedded(String),
+    File(PathBuf),
+    /// Name of the environment variable
+    Env(String),
+    /// The `path=expr` override as written
+    Override(String),
+    /// Set from code with `set_value`
+    Value,
+}
+
+impl Display for OmlSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OmlSource::Embedded(name) => write!(f, "embedded {}", name),
+            OmlSource::File(path) => write!(f, "file {}", path.display()),
+            OmlSource::Env(name) => write!(f, "environment variable {}", name),
+            OmlSource::Override(item) => write!(f, "override {}", item),
+            OmlSource::Value => write!(f, "value set from code"),
+        }
+    }
+}
+
+/// Conditions a value depends on, as `(path of the @if, condition, whether it must hold)`
+type Guard = Vec<(String, OmlExpr, bool)>;
+
+/// Stacks configuration sources into one `OmlExpr`, each source overrides the ones added before it
+///
+/// The usual order is embedded defaults, files, directories, environment, command-line
+/// overrides and values set from code.
+#[derive(Debug, Clone)]
+pub struct OmlBuilder {
+    expr: OmlExpr,
+    /// Sources of every path set so far, nested paths are covered by their nearest ancestor
+    ///
+    /// A value set by a conditional layer keeps the sources below it, the last one whose
+    /// conditions hold is the one that set it.
+    sources: IndexMap<String, Vec<(OmlSource, Guard)>>,
+}
+
+impl Default for OmlBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OmlBuilder {
+    pub fn new() -> Self {
+        OmlBuilder {
+            expr: OmlExpr::None,
+            sources: IndexMap::new(),
+        }
+    }
+
+    pub fn add_str(mut self, name: &str, content: &str) -> Result<Self, OmlError> {
+        let expr = OmlExpr::from_str(content)
+            .map_err(|err| OmlError::Parse(format!("{}: {}", name, err)))?;
+        self.add_layer(expr, &OmlSource::Embedded(name.to_string()));
+        Ok(self)
+    }
+
+    pub fn add_file(mut self, path: impl AsRef<Path>) -> Result<Self, OmlError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| OmlError::Io(format!("{}: {}", path.display(), err)))?;
+        let expr = OmlExpr::from_str(&content)
+            .map_err(|err| OmlError::Parse(format!("{}: {}", path.display(), err)))?;
+        self.add_layer(expr, &OmlSource::File(path.to_path_buf()));
+        Ok(self)
+    }
+
+    /// Every `.oml` file of a directory, in file name order
+    pub fn add_dir(mut self, path: impl AsRef<Path>) -> Result<Self, OmlError> {
+        let path = path.as_ref();
+        let io_error = |err: std::io::Error| OmlError::Io(format!("{}: {}", path.display(), err));
+        let mut files = vec![];
+        for entry in std::fs::read_dir(path).map_err(io_error)? {
+            let file = entry.map_err(io_error)?.path();
+            if file.is_file() && file.extension().map(|ext| ext == "oml").unwrap_or(false) {
+                files.push(file);
+            }
+        }
+        files.sort();
+        for file in files.into_iter() {
+            self = self.add_file(file)?;
+        }
+        Ok(self)
+    }
+
+    /// Variables named `{prefix}__A__B` set `a.b`, see `OmlExpr::apply_env`
+    pub fn add_env(self, prefix: &str) -> Result<Self, OmlError> {
+        self.add_env_vars(prefix, std::env::vars())
+    }
+
+    pub(crate) fn add_env_vars(
+        mut self,
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, OmlError> {
+        for (name, path, value) in env_overrides(prefix, vars).into_iter() {
+            self.expr.apply_override(&path, &value)?;
+            self.set_source(&path, OmlSource::Env(name));
+        }
+        Ok(self)
+    }
+
+    /// `path=expr` pairs, see `OmlExpr::apply_overrides`
+    pub fn add_overrides<I, S>(mut self, overrides: I) -> Result<Self, OmlError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for item in overrides.into_iter() {
+            let item = item.as_ref();
+            self.expr.apply_overrides([item])?;
+            let path = item.split_once('=').map(|(path, _)| path.trim()).unwrap();
+            self.set_source(path, OmlSource::Override(item.to_string()));
+        }
+        Ok(self)
+    }
+
+    pub fn set_value(mut self, path: &str, value: OmlValue) -> Result<Self, OmlError> {
+        self.expr.set_at(path, &value.into())?;
+        self.set_source(path, OmlSource::Value);
+        Ok(self)
+    }
+
+    pub fn build(&self) -> OmlExpr {
+        self.expr.clone()
+    }
+
+    /// Source that set the value at `path`, e.g. `db.pool` or `servers.0.port`
+    ///
+    /// The conditions of conditional layers are evaluated against the built value, a layer
+    /// whose condition doesn't hold or can't be evaluated hasn't set anything.
+    pub fn provenance(&self, path: &str) -> Option<&OmlSource> {
+        let value = self.expr.evalute().ok();
+        let holds = |guard: &Guard| {
+            (guard.iter()).all(|(scope, cond, holds)| match &value {
+                Some(value) => cond.holds(scope, value) == *holds,
+                None => false,
+            })
+        };
+        let mut path = path;
+        loop {
+            let sources = self.sources.get(path).into_iter().flatten();
+            if let Some((source, _)) = sources.rev().find(|(_, guard)| holds(guard)) {
+                return Some(source);
+            }
+            path = &path[..path.rfind('.')?];
+        }
+    }
+
+    fn add_layer(&mut self, expr: OmlExpr, source: &OmlSource) {
+        let mut paths = vec![];
+        Self::collect_paths(&expr, "", &vec![], &mut paths);
+        for (path, guard) in paths.into_iter() {
+            self.add_source(&path, source.clone(), guard);
+        }
+        let base = std::mem::replace(&mut self.expr, OmlExpr::None);
+        self.expr = Self::merge(expr, base);
+    }
+
+    /// Unlike `OmlExpr::apply`, arrays and values of the top layer replace the base instead of
+    /// extending or being replaced by it
+    fn merge(top: OmlExpr, base: OmlExpr) -> OmlExpr {
+        match (top, base) {
+            (OmlExpr::Map(mut top), OmlExpr::Map(base)) => {
+                let mut ret = IndexMap::new();
+                for (key, value) in base.into_iter() {
+                    let value = match top.shift_remove(&key) {
+                        // asserts are keyed by source offset, both layers keep theirs
+                        Some(top_value) if key.starts_with("@assert") => {
+                            ret.insert(format!("{}_{}", key, ret.len()), top_value);
+                            value
+                        }
+                        Some(top_value) => Self::merge(top_value, value),
+                        None => value,
+                    };
+                    ret.insert(key, value);
+                }
+                ret.extend(top);
+                OmlExpr::Map(ret)
+            }
+            // every branch of a conditional block is layered over the base, which is also the
+            // fallback when no condition holds, so the groups the block doesn't set are kept
+            (OmlExpr::IfAnno(mut if_anno), base) => {
+                for (_, expr) in if_anno.exprs.iter_mut() {
+                    let branch = std::mem::replace(expr, OmlExpr::None);
+                    *expr = Self::merge(branch, base.clone());
+                }
+                let default = match if_anno.default.take() {
+                    Some(default) => Self::merge(*default, base),
+                    None => base,
+                };
+                if_anno.default = Some(Box::new(default));
+                OmlExpr::IfAnno(if_anno)
+            }
+            // a layer over a conditional base applies whichever branch is taken
+            (top @ OmlExpr::Map(_), OmlExpr::IfAnno(mut if_anno)) => {
+                for (_, expr) in if_anno.exprs.iter_mut() {
+                    let branch = std::mem::replace(expr, OmlExpr::None);
+                    *expr = Self::merge(top.clone(), branch);
+                }
+                let default = if_anno.default.take().map(|default| *default);
+                let default = Self::merge(top, default.unwrap_or(OmlExpr::None));
+                if_anno.default = Some(Box::new(default));
+                OmlExpr::IfAnno(if_anno)
+            }
+            (top, _) => top,
+        }
+    }
+
+    /// Paths of the values a layer sets with the conditions they depend on, groups and
+    /// conditional branches are descended into
+    fn collect_paths(expr: &OmlExpr, path: &str, guard: &Guard, paths: &mut Vec<(String, Guard)>) {
+        match expr {
+            OmlExpr::IfAnno(if_anno) => {
+                // a branch is taken when its condition holds and none of the earlier ones do
+                let mut guard = guard.clone();
+                for (cond, expr) in if_anno.exprs.iter() {
+                    let mut branch = guard.clone();
+                    branch.push((path.to_string(), cond.clone(), true));
+                    Self::collect_paths(expr, path, &branch, paths);
+                    guard.push((path.to_string(), cond.clone(), false));
+                }
+                if let Some(expr) = &if_anno.default {
+                    Self::collect_paths(expr, path, &guard, paths);
+                }
+            }
+            OmlExpr::Map(map) => {
+                // `@assert` entries set no value
+                for (key, value) in map.iter().filter(|(key, _)| !key.starts_with('@')) {
+                    Self::collect_paths(value, &join_path(path, key), guard, paths);
+                }
+            }
+            _ => paths.push((path.to_string(), guard.clone())),
+        }
+    }
+
+    fn set_source(&mut self, path: &str, source: OmlSource) {
+        self.add_source(path, source, vec![]);
+    }
+
+    fn add_source(&mut self, path: &str, source: OmlSource, guard: Guard) {
+        // an unconditional value replaces everything below it, the values beside it keep their
+        // source
+        if guard.is_empty() {
+            self.sources.retain(|key, _| {
+                key != path && !(key.starts_with(path) && key[path.len()..].starts_with('.'))
+            });
+        }
+        let sources = self.sources.entry(path.to_string()).or_default();
+        sources.push((source, guard));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::ast::join_path;
+use crate::ast::oml_error::OmlError;
+use crate::{IndexMap, OmlExpr, OmlValue};
+use std::collections::HashMap;
+
+/// Typed loader generated by `#[derive(OmlConfig)]`
+pub trait OmlConfig: FromOmlValue {
+    fn from_oml_value(value: &OmlValue) -> Result<Self, OmlError> {
+        <Self as FromOmlValue>::from_oml_value(value, "")
+    }
+
+    fn from_oml_expr(expr: &OmlExpr) -> Result<Self, OmlError> {
+        <Self as OmlConfig>::from_oml_value(&expr.evalute()?)
+    }
+
+    fn from_oml_str(content: &str) -> Result<Self, OmlError> {
+        <Self as OmlConfig>::from_oml_expr(&OmlExpr::from_str(content)?)
+    }
+
+    /// Starting `.oml` document with every field set to its default or a placeholder
+    ///
+    /// Fails for structs with scalar fields, oml only has keys inside groups.
+    fn oml_template() -> Result<String, OmlError> {
+        let mut entries = vec![];
+        Self::oml_template_entries("", &[], &mut entries);
+        OmlTemplateEntry::render(&entries)
+    }
+}
+
+/// One line group of a generated template
+#[derive(Debug, Clone, PartialEq)]
+pub enum OmlTemplateEntry {
+    Group((String, Vec<String>)),
+    Field((String, Vec<String>, String)),
+}
+
+/// (key, docs, value) of a field in a rendered group
+type TemplateField = (String, Vec<String>, String);
+
+impl OmlTemplateEntry {
+    fn render(entries: &[OmlTemplateEntry]) -> Result<String, OmlError> {
+        // (docs, fields) of every group, fields of the same group are written under one header
+        let mut groups: IndexMap<String, (Vec<String>, Vec<TemplateField>)> = IndexMap::new();
+        let mut group = "".to_string();
+        for entry in entries.iter() {
+            match entry {
+                OmlTemplateEntry::Group((path, docs)) => {
+                    group = path.clone();
+                    let (group_docs, _) = groups.entry(path.clone()).or_default();
+                    if group_docs.is_empty() {
+                        *group_docs = docs.clone();
+                    }
+                }
+                OmlTemplateEntry::Field((key, docs, value)) => {
+                    let path = match group.is_empty() {
+                        true => key.clone(),
+                        false => format!("{}.{}", group, key),
+                    };
+                    // keys outside of any group can't be written in oml
+                    let Some((parent, name)) = path.rsplit_once('.') else {
+                        let msg = format!("{}: root fields must be inside a nested struct", path);
+                        return Err(OmlError::Serialize(msg));
+                    };
+                    let (_, fields) = groups.entry(parent.to_string()).or_default();
+                    fields.push((name.to_string(), docs.clone(), value.clone()));
+                }
+            }
+        }
+        let mut ret = "".to_string();
+        let write_docs = |ret: &mut String, docs: &[String]| {
+            for doc in docs.iter() {
+                ret.push_str(&format!("//{}\n", doc));
+            }
+        };
+        for (path, (docs, fields)) in groups.iter() {
+            if path.is_empty() && fields.is_empty() && docs.is_empty() {
+                continue;
+            }
+            if !ret.is_empty() {
+                ret.push('\n');
+            }
+            write_docs(&mut ret, docs);
+            if !path.is_empty() {
+                ret.push_str(&format!("[{}]\n", path));
+            }
+            for (key, docs, value) in fields.iter() {
+                write_docs(&mut ret, docs);
+                ret.push_str(&format!("{} = {}\n", key, value));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn type_error(path: &str, expected: &str, value: &OmlValue) -> OmlError {
+    let msg = format!("expected {}, found {}", expected, value.as_str());
+    OmlError::Deserialize((path.to_string(), msg))
+}
+
+/// Conversion of a single field, implemented for the supported field types
+pub trait FromOmlValue: Sized {
+    fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError>;
+
+    /// Value used when the key is absent and the field has no default
+    fn from_missing(path: &str) -> Result<Self, OmlError> {
+        Err(OmlError::Deserialize((
+            path.to_string(),
+            "missing field".to_string(),
+        )))
+    }
+
+    /// Text written into generated templates for fields without a literal default
+    fn oml_placeholder() -> String {
+        "none".to_string()
+    }
+
+    /// Nested config structs append their own group and return true
+    fn oml_template_entries(
+        _path: &str,
+        _docs: &[&str],
+        _entries: &mut Vec<OmlTemplateEntry>,
+    ) -> bool {
+        false
+    }
+}
+
+impl FromOmlValue for OmlValue {
+    fn from_oml_value(value: &OmlValue, _path: &str) -> Result<Self, OmlError> {
+        Ok(value.clone())
+    }
+
+    fn from_missing(_path: &str) -> Result<Self, OmlError> {
+        Ok(OmlValue::None)
+    }
+}
+
+impl FromOmlValue for bool {
+    fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError> {
+        value
+            .as_bool()
+            .ok_or_else(|| type_error(path, "bool", value))
+    }
+
+    fn oml_placeholder() -> String {
+        "false".to_string()
+    }
+}
+
+impl FromOmlValue for String {
+    fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError> {
+        match value {
+            OmlValue::String(s) => Ok(s.clone()),
+            _ => Err(type_error(path, "string", value)),
+        }
+    }
+
+    fn oml_placeholder() -> String {
+        "\"\"".to_string()
+    }
+}
+
+macro_rules! impl_from_oml_int {
+    ($($ty:ty),*) => {
+        $(impl FromOmlValue for $ty {
+            fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError> {
+                let n = value
+                    .as_int()
+                    .ok_or_else(|| type_error(path, stringify!($ty), value))?;
+                <$ty>::try_from(n).map_err(|_| type_error(path, stringify!($ty), value))
+            }
+
+            fn oml_placeholder() -> String {
+                "0".to_string()
+            }
+        })*
+    };
+}
+
+impl_from_oml_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_from_oml_float {
+    ($($ty:ty),*) => {
+        $(impl FromOmlValue for $ty {
+            fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError> {
+                match value {
+                    OmlValue::Float64(f) => Ok(*f as $ty),
+                    OmlValue::Int64(n) => Ok(*n as $ty),
+                    _ => Err(type_error(path, stringify!($ty), value)),
+                }
+            }
+
+            fn oml_placeholder() -> String {
+                "0.0".to_string()
+            }
+        })*
+    };
+}
+
+impl_from_oml_float!(f32, f64);
+
+impl<T: FromOmlValue> FromOmlValue for Option<T> {
+    fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError> {
+        match value {
+            OmlValue::None => Ok(None),
+            value => T::from_oml_value(value, path).map(Some),
+        }
+    }
+
+    fn from_missing(_path: &str) -> Result<Self, OmlError> {
+        Ok(None)
+    }
+}
+
+impl<T: FromOmlValue> FromOmlValue for Vec<T> {
+    fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError> {
+        match value {
+            OmlValue::Array(arr) => (arr.iter().enumerate())
+                .map(|(index, item)| T::from_oml_value(item, &join_path(path, index)))
+                .collect(),
+            _ => Err(type_error(path, "array", value)),
+        }
+    }
+
+    fn oml_placeholder() -> String {
+        "[]".to_string()
+    }
+}
+
+impl<T: FromOmlValue> FromOmlValue for IndexMap<String, T> {
+    fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError> {
+        match value {
+            OmlValue::Map(map) => (map.iter())
+                .map(|(k, v)| Ok((k.clone(), T::from_oml_value(v, &join_path(path, k))?)))
+                .collect(),
+            _ => Err(type_error(path, "map", value)),
+        }
+    }
+
+    fn oml_placeholder() -> String {
+        "{}".to_string()
+    }
+}
+
+impl<T: FromOmlValue> FromOmlValue for HashMap<String, T> {
+    fn from_oml_value(value: &OmlValue, path: &str) -> Result<Self, OmlError> {
+        let map: IndexMap<String, T> = FromOmlValue::from_oml_value(value, path)?;
+        Ok(map.into_iter().collect())
+    }
+
+    fn oml_placeholder() -> String {
+        "{}".to_string()
+    }
+}
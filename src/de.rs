@@ -0,0 +1,335 @@
+use crate::ast::join_path;
+use crate::ast::oml_error::OmlError;
+use crate::IndexMap;
+use crate::{OmlExpr, OmlValue};
+use serde::de::{self, Deserialize, DeserializeOwned, Visitor};
+use std::fmt::{Display, Formatter};
+
+/// Evaluate an oml document and deserialize the result into `T`
+pub fn from_str<T: DeserializeOwned>(content: &str) -> Result<T, OmlError> {
+    let value = OmlExpr::from_str(content)?.evalute()?;
+    from_value(&value)
+}
+
+/// Deserialize an evaluated value into `T`
+pub fn from_value<T: DeserializeOwned>(value: &OmlValue) -> Result<T, OmlError> {
+    T::deserialize(OmlDeserializer::new(value, ""))
+}
+
+impl de::Error for OmlError {
+    fn custom<T: Display>(msg: T) -> Self {
+        OmlError::Deserialize(("".to_string(), msg.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for OmlValue {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(OmlValueVisitor)
+    }
+}
+
+struct OmlValueVisitor;
+
+impl<'de> Visitor<'de> for OmlValueVisitor {
+    type Value = OmlValue;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "any oml value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<OmlValue, E> {
+        Ok(OmlValue::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<OmlValue, E> {
+        Ok(OmlValue::Int64(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<OmlValue, E> {
+        // rejected like on serialization, rounding to a float would lose digits
+        match i64::try_from(v) {
+            Ok(v) => Ok(OmlValue::Int64(v)),
+            Err(_) => Err(E::custom(format!("integer {} out of range", v))),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<OmlValue, E> {
+        Ok(OmlValue::Float64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<OmlValue, E> {
+        Ok(OmlValue::String(v.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<OmlValue, E> {
+        Ok(OmlValue::String(v))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<OmlValue, E> {
+        Ok(OmlValue::None)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<OmlValue, E> {
+        Ok(OmlValue::None)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<OmlValue, D::Error> {
+        OmlValue::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<OmlValue, A::Error> {
+        let mut arr = vec![];
+        while let Some(item) = seq.next_element()? {
+            arr.push(item);
+        }
+        Ok(OmlValue::Array(arr))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut access: A) -> Result<OmlValue, A::Error> {
+        let mut map = IndexMap::new();
+        while let Some((key, value)) = access.next_entry::<String, OmlValue>()? {
+            map.insert(key, value);
+        }
+        Ok(OmlValue::Map(map))
+    }
+}
+
+/// Attach the path of the value being deserialized to errors that don't carry one yet
+fn with_path(err: OmlError, path: &str) -> OmlError {
+    match err {
+        OmlError::Deserialize((err_path, msg)) if err_path.is_empty() => {
+            OmlError::Deserialize((path.to_string(), msg))
+        }
+        err => err,
+    }
+}
+
+pub struct OmlDeserializer<'de> {
+    value: &'de OmlValue,
+    path: String,
+}
+
+impl<'de> OmlDeserializer<'de> {
+    pub fn new(value: &'de OmlValue, path: &str) -> Self {
+        Self {
+            value,
+            path: path.to_string(),
+        }
+    }
+
+    fn invalid_type(&self, exp: &dyn de::Expected) -> OmlError {
+        let unexp = match self.value {
+            OmlValue::None => de::Unexpected::Unit,
+            OmlValue::Bool(b) => de::Unexpected::Bool(*b),
+            OmlValue::Int64(n) => de::Unexpected::Signed(*n),
+            OmlValue::Float64(f) => de::Unexpected::Float(*f),
+            OmlValue::String(s) => de::Unexpected::Str(s),
+            OmlValue::Array(_) => de::Unexpected::Seq,
+            OmlValue::Map(_) => de::Unexpected::Map,
+        };
+        with_path(de::Error::invalid_type(unexp, exp), &self.path)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for OmlDeserializer<'de> {
+    type Error = OmlError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, OmlError> {
+        let ret = match self.value {
+            OmlValue::None => visitor.visit_unit(),
+            OmlValue::Bool(b) => visitor.visit_bool(*b),
+            OmlValue::Int64(n) => visitor.visit_i64(*n),
+            OmlValue::Float64(f) => visitor.visit_f64(*f),
+            OmlValue::String(s) => visitor.visit_borrowed_str(s),
+            OmlValue::Array(arr) => visitor.visit_seq(SeqAccess {
+                iter: arr.iter().enumerate(),
+                path: &self.path,
+            }),
+            OmlValue::Map(map) => visitor.visit_map(MapAccess {
+                iter: map.iter(),
+                value: None,
+                path: &self.path,
+            }),
+        };
+        ret.map_err(|err| with_path(err, &self.path))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, OmlError> {
+        match self.value {
+            OmlValue::None => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, OmlError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, OmlError> {
+        // unit variants are written as strings, other variants as a single key map
+        let (variant, value) = match self.value {
+            OmlValue::String(s) => (s, None),
+            OmlValue::Map(map) if map.len() == 1 => {
+                let (key, value) = map.iter().next().unwrap();
+                (key, Some(value))
+            }
+            _ => return Err(self.invalid_type(&"string or map with a single key")),
+        };
+        let path = match value {
+            Some(_) => join_path(&self.path, variant),
+            None => self.path.clone(),
+        };
+        let ret = visitor.visit_enum(EnumAccess {
+            variant,
+            value,
+            path: &path,
+        });
+        ret.map_err(|err| with_path(err, &path))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct SeqAccess<'de, 'a> {
+    iter: std::iter::Enumerate<std::slice::Iter<'de, OmlValue>>,
+    path: &'a str,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for SeqAccess<'de, 'a> {
+    type Error = OmlError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, OmlError> {
+        match self.iter.next() {
+            Some((index, value)) => {
+                let path = join_path(self.path, index);
+                let ret = seed.deserialize(OmlDeserializer::new(value, &path));
+                ret.map(Some).map_err(|err| with_path(err, &path))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapAccess<'de, 'a> {
+    iter: indexmap::map::Iter<'de, String, OmlValue>,
+    value: Option<(&'de String, &'de OmlValue)>,
+    path: &'a str,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for MapAccess<'de, 'a> {
+    type Error = OmlError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, OmlError> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some((key, value));
+                seed.deserialize(de::value::BorrowedStrDeserializer::new(key))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, OmlError> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value is missing"))?;
+        let path = join_path(self.path, key);
+        let ret = seed.deserialize(OmlDeserializer::new(value, &path));
+        ret.map_err(|err| with_path(err, &path))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumAccess<'de, 'a> {
+    variant: &'de str,
+    value: Option<&'de OmlValue>,
+    path: &'a str,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for EnumAccess<'de, 'a> {
+    type Error = OmlError;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), OmlError> {
+        let variant = de::value::BorrowedStrDeserializer::new(self.variant);
+        Ok((seed.deserialize(variant)?, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for EnumAccess<'de, 'a> {
+    type Error = OmlError;
+
+    fn unit_variant(self) -> Result<(), OmlError> {
+        match self.value {
+            None | Some(OmlValue::None) => Ok(()),
+            Some(value) => {
+                Err(OmlDeserializer::new(value, self.path).invalid_type(&"unit variant"))
+            }
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, OmlError> {
+        seed.deserialize(self.deserializer()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, OmlError> {
+        de::Deserializer::deserialize_any(self.deserializer()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, OmlError> {
+        de::Deserializer::deserialize_any(self.deserializer()?, visitor)
+    }
+}
+
+impl<'de, 'a> EnumAccess<'de, 'a> {
+    fn deserializer(&self) -> Result<OmlDeserializer<'de>, OmlError> {
+        match self.value {
+            Some(value) => Ok(OmlDeserializer::new(value, self.path)),
+            None => Err(OmlError::Deserialize((
+                self.path.to_string(),
+                format!("variant {} expects a value", self.variant),
+            ))),
+        }
+    }
+}
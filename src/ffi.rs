@@ -32,7 +32,7 @@ pub extern "C" fn oml_expr_from_str(
         }
         Err(err) => {
             unsafe { *ppexpr = std::ptr::null_mut() };
-            unsafe { *pperr = CString::new(err).unwrap().into_raw() };
+            unsafe { *pperr = CString::new(err.to_string()).unwrap().into_raw() };
             false.as_cint()
         }
     }
@@ -102,7 +102,7 @@ pub extern "C" fn oml_expr_evalute(
         }
         Err(err) => {
             unsafe { *ppval = std::ptr::null_mut() };
-            unsafe { *pperr = CString::new(err).unwrap().into_raw() };
+            unsafe { *pperr = CString::new(err.to_string()).unwrap().into_raw() };
             false
         }
     };
@@ -271,8 +271,7 @@ pub extern "C" fn oml_value_get_keys(pval: *mut c_void, ppath: *const c_char) ->
         .get_with_path(path)
         .map(|a| {
             a.as_map().map(|map| {
-                let mut keys: Vec<_> = map.keys().map(|a| &a[..]).collect();
-                keys.sort();
+                let keys: Vec<_> = map.keys().map(|a| &a[..]).collect();
                 CString::new(keys.join("#")).unwrap().into_raw() as *const i8
             })
         })
@@ -0,0 +1,31 @@
+use crate::ast::oml_error::OmlError;
+use crate::{OmlExpr, OmlValue};
+
+impl OmlValue {
+    /// Compact JSON, none becomes null
+    pub fn to_json_string(&self) -> Result<String, OmlError> {
+        serde_json::to_string(self).map_err(|err| OmlError::Serialize(err.to_string()))
+    }
+
+    /// Indented JSON, none becomes null
+    pub fn to_json_string_pretty(&self) -> Result<String, OmlError> {
+        serde_json::to_string_pretty(self).map_err(|err| OmlError::Serialize(err.to_string()))
+    }
+
+    /// Syntax errors are reported as `Parse`, values oml can't hold as `Deserialize`
+    pub fn from_json(content: &str) -> Result<Self, OmlError> {
+        serde_json::from_str(content).map_err(|err| match err.classify() {
+            serde_json::error::Category::Data => {
+                OmlError::Deserialize(("".to_string(), err.to_string()))
+            }
+            _ => OmlError::Parse(err.to_string()),
+        })
+    }
+}
+
+impl OmlExpr {
+    /// Literal expression tree of a JSON document, ready to be merged with `apply`
+    pub fn from_json(content: &str) -> Result<Self, OmlError> {
+        Ok(OmlValue::from_json(content)?.into())
+    }
+}
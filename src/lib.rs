@@ -1,9 +1,38 @@
+extern crate self as oml;
+
 mod ast;
+mod builder;
+mod config;
+#[cfg(feature = "serde")]
+mod de;
 pub mod ffi;
+#[cfg(feature = "json")]
+mod json;
+mod overrides;
+#[cfg(feature = "serde")]
+mod ser;
 mod string_utils;
+#[cfg(feature = "toml")]
+mod toml;
+mod yaml;
 
+pub use ast::oml_cst::{OmlCstKind, OmlCstNode, OmlDocument};
+pub use ast::oml_env::EnvOptions;
+pub use ast::oml_error::OmlError;
 pub use ast::oml_expr::OmlExpr;
+pub use ast::oml_format::{format, FormatOptions};
 pub use ast::oml_value::OmlValue;
+pub use builder::{OmlBuilder, OmlSource};
+pub use config::{FromOmlValue, OmlConfig, OmlTemplateEntry};
+pub use indexmap::IndexMap;
+
+#[cfg(feature = "serde")]
+pub use de::{from_str, from_value, OmlDeserializer};
+#[cfg(feature = "serde")]
+pub use ser::{to_value, OmlSerializer};
+
+#[cfg(feature = "derive")]
+pub use oml_derive::{include_oml, oml, OmlConfig};
 
 #[cfg(test)]
 pub mod test;
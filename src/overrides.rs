@@ -0,0 +1,135 @@
+use crate::ast::oml_error::OmlError;
+use crate::ast::oml_printer::is_id;
+use crate::{IndexMap, OmlExpr};
+
+/// `(name, path, value)` of the variables starting with `{prefix}__`, sorted by path
+pub(crate) fn env_overrides(
+    prefix: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<(String, String, String)> {
+    let prefix = format!("{}__", prefix.trim_end_matches('_'));
+    let mut ret: Vec<_> = (vars.into_iter())
+        .filter_map(|(name, value)| {
+            let path = name
+                .strip_prefix(&prefix)?
+                .replace("__", ".")
+                .to_lowercase();
+            Some((name, path, value))
+        })
+        .collect();
+    // the process environment has no order, sorting keeps the result reproducible
+    ret.sort_by(|a, b| a.1.cmp(&b.1));
+    ret
+}
+
+impl OmlExpr {
+    /// Replace values from `path=expr` pairs, e.g. `hello.value=30` or `db.url=$"{host}:5432"`
+    ///
+    /// Values are OML expressions, so strings need quotes and names resolve from the
+    /// group of the overridden key just as they would in the file.
+    pub fn apply_overrides<I, S>(&mut self, overrides: I) -> Result<(), OmlError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for item in overrides.into_iter() {
+            let item = item.as_ref();
+            let (path, value) = match item.split_once('=') {
+                Some((path, value)) if !path.trim().is_empty() => (path.trim(), value),
+                _ => return Err(OmlError::Parse(format!("invalid override: {}", item))),
+            };
+            self.apply_override(path, value)?;
+        }
+        Ok(())
+    }
+
+    /// Replace values from environment variables named `{prefix}__A__B`, which set `a.b`
+    pub fn apply_env(&mut self, prefix: &str) -> Result<(), OmlError> {
+        self.apply_vars(prefix, std::env::vars())
+    }
+
+    pub(crate) fn apply_vars(
+        &mut self,
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), OmlError> {
+        for (_, path, value) in env_overrides(prefix, vars).iter() {
+            self.apply_override(path, value)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn apply_override(&mut self, path: &str, value: &str) -> Result<(), OmlError> {
+        let value = OmlExpr::from_expr_str(value)
+            .map_err(|err| OmlError::Parse(format!("{}: {}", path, err)))?;
+        self.set_at(path, &value)
+    }
+
+    /// Replace the value at `path`, e.g. `servers.0.port`
+    ///
+    /// Missing groups are created, but existing values are never turned into groups and arrays
+    /// are never extended.
+    pub(crate) fn set_at(&mut self, path: &str, value: &OmlExpr) -> Result<(), OmlError> {
+        let items: Vec<_> = path.split('.').collect();
+        let is_index = |item: &str| !item.is_empty() && item.chars().all(|c| c.is_ascii_digit());
+        if !items.iter().all(|item| is_id(item) || is_index(item)) {
+            return Err(OmlError::Parse(format!("invalid override path: {}", path)));
+        }
+        Self::set_items(self, path, &items, 0, value)
+    }
+
+    fn set_items(
+        expr: &mut OmlExpr,
+        path: &str,
+        items: &[&str],
+        depth: usize,
+        value: &OmlExpr,
+    ) -> Result<(), OmlError> {
+        let item = match items.get(depth) {
+            Some(item) => *item,
+            None => {
+                *expr = value.clone();
+                return Ok(());
+            }
+        };
+        let error = |msg: String| {
+            let parent = match depth {
+                0 => "the root".to_string(),
+                _ => items[..depth].join("."),
+            };
+            OmlError::Parse(format!("{}: {} {}", path, parent, msg))
+        };
+        let index = item.parse::<usize>().ok();
+        if matches!(expr, OmlExpr::None) && index.is_none() {
+            *expr = OmlExpr::Map(IndexMap::new());
+        }
+        match (expr, index) {
+            // every branch keeps its condition, the default covers the case where none holds
+            (OmlExpr::IfAnno(if_anno), _) => {
+                for (_, expr) in if_anno.exprs.iter_mut() {
+                    Self::set_items(expr, path, items, depth, value)?;
+                }
+                match &mut if_anno.default {
+                    Some(default) => Self::set_items(default, path, items, depth, value),
+                    None => {
+                        let mut default = OmlExpr::None;
+                        if Self::set_items(&mut default, path, items, depth, value).is_ok() {
+                            if_anno.default = Some(Box::new(default));
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            (OmlExpr::Map(map), None) => {
+                let expr = map.entry(item.to_string()).or_insert(OmlExpr::None);
+                Self::set_items(expr, path, items, depth + 1, value)
+            }
+            (OmlExpr::Array(arr), Some(index)) => match arr.get_mut(index) {
+                Some(expr) => Self::set_items(expr, path, items, depth + 1, value),
+                None => Err(error(format!("has no item {}", index))),
+            },
+            (_, Some(_)) => Err(error("is not an array".to_string())),
+            (_, None) => Err(error("is not a group".to_string())),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use crate::ast::oml_error::OmlError;
+use crate::{IndexMap, OmlValue};
+use serde::ser::{self, Serialize, Serializer};
+use std::fmt::Display;
+
+/// Convert any serializable value into an `OmlValue`
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<OmlValue, OmlError> {
+    value.serialize(OmlSerializer)
+}
+
+impl ser::Error for OmlError {
+    fn custom<T: Display>(msg: T) -> Self {
+        OmlError::Serialize(msg.to_string())
+    }
+}
+
+impl Serialize for OmlValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            OmlValue::None => serializer.serialize_none(),
+            OmlValue::Bool(b) => serializer.serialize_bool(*b),
+            OmlValue::Int64(n) => serializer.serialize_i64(*n),
+            OmlValue::Float64(f) => serializer.serialize_f64(*f),
+            OmlValue::String(s) => serializer.serialize_str(s),
+            OmlValue::Array(arr) => serializer.collect_seq(arr),
+            OmlValue::Map(map) => serializer.collect_map(map),
+        }
+    }
+}
+
+/// Serializer producing `OmlValue`, enums use the same layout `from_value` reads
+pub struct OmlSerializer;
+
+impl Serializer for OmlSerializer {
+    type Ok = OmlValue;
+    type Error = OmlError;
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVec;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<OmlValue, OmlError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<OmlValue, OmlError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<OmlValue, OmlError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::Int64(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<OmlValue, OmlError> {
+        match i64::try_from(v) {
+            Ok(v) => self.serialize_i64(v),
+            Err(_) => Err(OmlError::Serialize(format!("integer {} out of range", v))),
+        }
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<OmlValue, OmlError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<OmlValue, OmlError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<OmlValue, OmlError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<OmlValue, OmlError> {
+        self.serialize_i128(v as i128)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<OmlValue, OmlError> {
+        match i64::try_from(v) {
+            Ok(v) => self.serialize_i64(v),
+            Err(_) => Err(OmlError::Serialize(format!("integer {} out of range", v))),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<OmlValue, OmlError> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::Float64(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::Array(
+            v.iter().map(|b| OmlValue::Int64(*b as i64)).collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<OmlValue, OmlError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<OmlValue, OmlError> {
+        Ok(OmlValue::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<OmlValue, OmlError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<OmlValue, OmlError> {
+        let mut map = IndexMap::new();
+        map.insert(variant.to_string(), to_value(value)?);
+        Ok(OmlValue::Map(map))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec, OmlError> {
+        Ok(SerializeVec {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec, OmlError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, OmlError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, OmlError> {
+        Ok(SerializeVec {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, OmlError> {
+        Ok(SerializeMap {
+            variant: None,
+            map: IndexMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap, OmlError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap, OmlError> {
+        Ok(SerializeMap {
+            variant: Some(variant),
+            map: IndexMap::new(),
+            key: None,
+        })
+    }
+}
+
+/// Wrap the content of a non-unit enum variant as `{ variant: content }`
+fn wrap_variant(variant: Option<&'static str>, value: OmlValue) -> OmlValue {
+    match variant {
+        Some(variant) => OmlValue::Map([(variant.to_string(), value)].into_iter().collect()),
+        None => value,
+    }
+}
+
+pub struct SerializeVec {
+    variant: Option<&'static str>,
+    items: Vec<OmlValue>,
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = OmlValue;
+    type Error = OmlError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), OmlError> {
+        self.items.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<OmlValue, OmlError> {
+        Ok(wrap_variant(self.variant, OmlValue::Array(self.items)))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = OmlValue;
+    type Error = OmlError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), OmlError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<OmlValue, OmlError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = OmlValue;
+    type Error = OmlError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), OmlError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<OmlValue, OmlError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec {
+    type Ok = OmlValue;
+    type Error = OmlError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), OmlError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<OmlValue, OmlError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeMap {
+    variant: Option<&'static str>,
+    map: IndexMap<String, OmlValue>,
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = OmlValue;
+    type Error = OmlError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), OmlError> {
+        // scalar keys are stored by their text, like the keys of a parsed document
+        self.key = Some(match to_value(key)? {
+            OmlValue::Array(_) | OmlValue::Map(_) | OmlValue::None => {
+                return Err(OmlError::Serialize("map key must be a scalar".to_string()))
+            }
+            key => key.as_str(),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), OmlError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| OmlError::Serialize("map value without key".to_string()))?;
+        self.map.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<OmlValue, OmlError> {
+        Ok(wrap_variant(self.variant, OmlValue::Map(self.map)))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = OmlValue;
+    type Error = OmlError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), OmlError> {
+        self.map.insert(key.to_string(), to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<OmlValue, OmlError> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = OmlValue;
+    type Error = OmlError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), OmlError> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<OmlValue, OmlError> {
+        ser::SerializeMap::end(self)
+    }
+}
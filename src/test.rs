@@ -1,4 +1,4 @@
-use crate::OmlExpr;
+use crate::{OmlError, OmlExpr};
 
 #[test]
 fn test1() {
@@ -18,3 +18,1293 @@ name = $"hello world {value + 12}"
     };
     assert_eq!(root["hello"]["name"].as_str(), "hello world 42");
 }
+
+#[test]
+fn test_in_operator() {
+    let oml_str = r#"
+[hello]
+region = "us-west"
+flags = ["debug", "trace"]
+limits = { cpu: 2, mem: 4 }
+a = region in ["us-east", "us-west"]
+b = "debug" not in flags
+c = "cpu" in limits
+d = "west" in region
+e = 1 + 2 * 3 - 4 in [3, 5]
+f = 1 < 2 < 3
+"#;
+    let root = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    assert_eq!(root["hello"]["a"].as_bool(), Some(true));
+    assert_eq!(root["hello"]["b"].as_bool(), Some(false));
+    assert_eq!(root["hello"]["c"].as_bool(), Some(true));
+    assert_eq!(root["hello"]["d"].as_bool(), Some(true));
+    assert_eq!(root["hello"]["e"].as_bool(), Some(true));
+    assert_eq!(root["hello"]["f"].as_bool(), Some(true));
+}
+
+#[test]
+fn test_null_literal() {
+    let oml_str = r#"
+[hello]
+value = null
+a = value == none
+b = value != 12
+c = $"value is {value}"
+d = c + "!"
+"#;
+    let root = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    assert!(root["hello"]["value"].is_none());
+    assert_eq!(root["hello"]["a"].as_bool(), Some(true));
+    assert_eq!(root["hello"]["b"].as_bool(), Some(true));
+    assert_eq!(root["hello"]["d"].as_str(), "value is none!");
+    let err = OmlExpr::from_str("[hello]\nvalue = none\nb = value + 1")
+        .unwrap()
+        .evalute()
+        .unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::At((
+            "hello.b".to_string(),
+            Box::new(OmlError::NoneOperand("+".to_string()))
+        ))
+    );
+    let err = OmlExpr::from_str("[hello]\nvalue = none\nb = -value")
+        .unwrap()
+        .evalute()
+        .unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::At((
+            "hello.b".to_string(),
+            Box::new(OmlError::NoneOperand("-".to_string()))
+        ))
+    );
+}
+
+#[test]
+fn test_format_spec() {
+    let oml_str = r#"
+[hello]
+ratio = 0.4567
+port = 80
+name = "api"
+a = $"{ratio:.2}|{port:05}|{name:>6}|{name:*^7}|{name:<5}|"
+b = $"{port:#x}|{port:b}|{-port:+06}|{{port}}|{{{port}}}"
+c = $"plain"
+"#;
+    let root = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    assert_eq!(
+        root["hello"]["a"].as_str(),
+        "0.46|00080|   api|**api**|api  |"
+    );
+    assert_eq!(
+        root["hello"]["b"].as_str(),
+        "0x50|1010000|-00080|{port}|{80}"
+    );
+    assert_eq!(root["hello"]["c"].as_str(), "plain");
+    let err = OmlExpr::from_str("[hello]\nname = \"api\"\na = $\"{name:x}\"")
+        .unwrap()
+        .evalute()
+        .unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::At((
+            "hello.a".to_string(),
+            Box::new(OmlError::FormatSpec(":x".to_string()))
+        ))
+    );
+    assert!(OmlExpr::from_str("[hello]\na = $\"{name:?}\"").is_err());
+}
+
+#[test]
+fn test_number_literal() {
+    let oml_str = "[a]\nratio = -0.5\nmax = 9223372036854775807\nbig = 18446744073709551615";
+    let root = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    assert_eq!(root["a"]["ratio"], crate::OmlValue::Float64(-0.5));
+    assert_eq!(root["a"]["max"].as_int(), Some(i64::MAX));
+    assert_eq!(root["a"]["big"].as_str(), "18446744073709551615");
+    let err = OmlExpr::from_str("[a]\nb = 1 % 0")
+        .unwrap()
+        .evalute()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "a.b: division by zero");
+    let err = OmlExpr::from_str("[a]\nb = 2 ** 64")
+        .unwrap()
+        .evalute()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "a.b: integer overflow: 2 ** 64");
+}
+
+#[test]
+fn test_comprehension() {
+    let oml_str = r#"
+[db]
+hosts = [ $"shard-{i}.db" for i in range(0, shards) if i != 3 ]
+shards = count + 1
+count = 4
+limits = { cpu: 2, mem: 4 }
+doubled = { k: v * 2 for k, v in limits }
+indexed = [ i * 10 + v for i, v in [1, 2] ]
+"#;
+    let root = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    assert_eq!(
+        root["db"]["hosts"].as_str(),
+        "[shard-0.db, shard-1.db, shard-2.db, shard-4.db]"
+    );
+    assert_eq!(root["db"]["doubled"]["cpu"].as_int(), Some(4));
+    assert_eq!(root["db"]["doubled"]["mem"].as_int(), Some(8));
+    assert_eq!(root["db"]["indexed"].as_str(), "[1, 12]");
+}
+
+#[test]
+fn test_name_scope() {
+    let oml_str = r#"
+[base]
+v = 1
+
+[app]
+v0 = 5
+a = root.base.v
+b = super.base.v
+list = [v0, v0 + 1, [v0 * 2]]
+
+[app.inner]
+c = super.v0
+d = root.app.list
+"#;
+    let root = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    assert_eq!(root["app"]["a"].as_int(), Some(1));
+    assert_eq!(root["app"]["b"].as_int(), Some(1));
+    // array items resolve names in the group that holds the array
+    assert_eq!(root["app"]["list"].as_str(), "[5, 6, [10]]");
+    assert_eq!(root["app"]["inner"]["c"].as_int(), Some(5));
+    assert_eq!(root["app"]["inner"]["d"][1].as_int(), Some(6));
+}
+
+#[test]
+fn test_spread() {
+    let oml_str = r#"
+[base]
+plugins = ["auth", "log"]
+defaults = { timeout: 10, retry: { count: 3 } }
+
+[app]
+plugins = ["first", ...root.base.plugins, "extra"]
+defaults = super.base.defaults
+a = { ...defaults, timeout: 30 }
+b = { timeout: 30, ...defaults }
+"#;
+    let root = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    assert_eq!(root["app"]["plugins"].as_str(), "[first, auth, log, extra]");
+    assert_eq!(root["app"]["a"]["timeout"].as_int(), Some(30));
+    assert_eq!(root["app"]["a"]["retry"]["count"].as_int(), Some(3));
+    assert_eq!(root["app"]["b"]["timeout"].as_int(), Some(10));
+}
+
+#[test]
+fn test_let() {
+    let oml_str = r#"
+[server]
+@let base = 8000
+@let step = 10
+port = base + step
+name = let a = 1, b = a + 1 in $"node-{a}-{b}"
+in_list = let x = 2 in x in [1, 2]
+"#;
+    let root = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    assert_eq!(root["server"]["port"].as_int(), Some(8010));
+    assert_eq!(root["server"]["name"].as_str(), "node-1-2");
+    assert_eq!(root["server"]["in_list"].as_bool(), Some(true));
+    assert!(root["server"].get("base").is_none());
+    assert!(root["server"].get("step").is_none());
+}
+
+#[test]
+fn test_functions() {
+    let oml_str = r#"
+[cluster]
+base = 8000
+@fn port_for(idx) = base + idx * 10
+@fn fact(n) = n <= 1 ? 1 : n * fact(n - 1)
+@fn forever(n) = forever(n + 1)
+api = port_for(1)
+ports = [ port_for(i) for i in range(0, 3) ]
+doubled = [1, 2, 3].map(x => x * 2)
+odd = ports.filter(p => p % 20 != 0).map(port_for)
+sum = [1, 2, 3].reduce((acc, x) => acc + x, 0)
+"#;
+    let mut root = OmlExpr::from_str(oml_str).unwrap();
+    root["cluster"]["base"].set_int(9000);
+    let value = root.evalute().unwrap();
+    assert_eq!(value["cluster"]["api"].as_int(), Some(9010));
+    assert_eq!(value["cluster"]["ports"].as_str(), "[9000, 9010, 9020]");
+    assert_eq!(value["cluster"]["doubled"].as_str(), "[2, 4, 6]");
+    assert_eq!(value["cluster"]["odd"].as_str(), "[99100]");
+    assert_eq!(value["cluster"]["sum"].as_int(), Some(6));
+    assert!(value["cluster"].get("port_for").is_none());
+    root["cluster"]["result"] = OmlExpr::from_str("[a]\nb = fact(5)").unwrap()["a"]["b"].clone();
+    assert_eq!(
+        root.evalute().unwrap()["cluster"]["result"].as_int(),
+        Some(120)
+    );
+    root["cluster"]["result"] = OmlExpr::from_str("[a]\nb = forever(0)").unwrap()["a"]["b"].clone();
+    let err = root.evalute().unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::At((
+            "cluster.result".to_string(),
+            Box::new(OmlError::CallDepth("forever".to_string()))
+        ))
+    );
+}
+
+#[test]
+fn test_template() {
+    let oml_str = r#"
+@template service(port, replicas = 1) {
+    addr = $"0.0.0.0:{port}"
+    replicas = replicas
+    health.url = $"http://localhost:{port}/health"
+    @if replicas > 1
+    mode = "cluster"
+}
+
+[base]
+port = 8000
+
+[services.api] = service(port: root.base.port + 80, replicas: 3)
+
+[services.worker] = service(port: 9000)
+replicas = 4
+"#;
+    let value = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    let api = &value["services"]["api"];
+    assert_eq!(api["addr"].as_str(), "0.0.0.0:8080");
+    assert_eq!(api["replicas"].as_int(), Some(3));
+    assert_eq!(
+        api["health"]["url"].as_str(),
+        "http://localhost:8080/health"
+    );
+    assert_eq!(api["mode"].as_str(), "cluster");
+    assert!(api.get("@port").is_none());
+    let worker = &value["services"]["worker"];
+    assert_eq!(worker["addr"].as_str(), "0.0.0.0:9000");
+    assert_eq!(worker["replicas"].as_int(), Some(4));
+    assert!(worker["mode"].is_none());
+    let err = OmlExpr::from_str("[a] = service(port: 1)").unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::Parse("undefined template: service".to_string())
+    );
+}
+
+#[test]
+fn test_match() {
+    let oml_str = r#"
+[deploy]
+env = "staging"
+score = 87
+replicas = match env { "prod" => 10, "staging" | "qa" => 3, _ => 1 }
+grade = match score {
+    90.. => "A",
+    80..90 => "B",
+    ..80 => "C",
+}
+tier = match replicas { 1..=3 => "small", 4..=10 => "large" }
+"#;
+    let mut root = OmlExpr::from_str(oml_str).unwrap();
+    let value = root.evalute().unwrap();
+    assert_eq!(value["deploy"]["replicas"].as_int(), Some(3));
+    assert_eq!(value["deploy"]["grade"].as_str(), "B");
+    assert_eq!(value["deploy"]["tier"].as_str(), "small");
+    root["deploy"]["env"].set_string("prod");
+    let value = root.evalute().unwrap();
+    assert_eq!(value["deploy"]["tier"].as_str(), "large");
+    root["deploy"]["env"].set_string("dev");
+    root["deploy"]["tier"] =
+        OmlExpr::from_str("[a]\nb = match replicas { 2..=3 => 0 }").unwrap()["a"]["b"].clone();
+    let err = root.evalute().unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::At((
+            "deploy.tier".to_string(),
+            Box::new(OmlError::NonExhaustive("1".to_string()))
+        ))
+    );
+}
+
+#[test]
+fn test_assert() {
+    let oml_str = r#"
+@assert root.pool.max_conn <= 1000, "pool too large"
+
+[pool]
+min_conn = 10
+max_conn = 20
+@assert max_conn >= min_conn, $"max must exceed min ({max_conn} < {min_conn})"
+@assert min_conn > 0
+
+[[servers]]
+port = 80
+@assert port < 1024
+"#;
+    let mut root = OmlExpr::from_str(oml_str).unwrap();
+    let value = root.evalute().unwrap();
+    assert!(value["pool"].get("max_conn").is_some());
+    assert_eq!(value["pool"].as_map().unwrap().len(), 2);
+    root["pool"]["min_conn"].set_int(2000);
+    root["pool"]["max_conn"].set_int(1500);
+    root["servers"][0]["port"].set_int(8080);
+    let err = root.evalute().unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::Assert(vec![
+            ("".to_string(), 1, "pool too large".to_string()),
+            (
+                "pool".to_string(),
+                oml_str.find("@assert max_conn").unwrap(),
+                "max must exceed min (1500 < 2000)".to_string()
+            ),
+            (
+                "servers.0".to_string(),
+                oml_str.find("@assert port").unwrap(),
+                "port < 1024".to_string()
+            ),
+        ])
+    );
+    assert!(err
+        .to_string()
+        .starts_with("assertion failed:\n  root: pool too large"));
+}
+
+#[test]
+fn test_map_order() {
+    let oml_str = r#"
+[zeta]
+b = 1
+a = { y: 2, x: 1 }
+c = a.x + b
+
+[alpha]
+value = 1
+"#;
+    let value = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    let keys: Vec<_> = value.as_map().unwrap().keys().cloned().collect();
+    assert_eq!(keys, vec!["zeta", "alpha"]);
+    let keys: Vec<_> = value["zeta"].as_map().unwrap().keys().cloned().collect();
+    assert_eq!(keys, vec!["b", "a", "c"]);
+    assert_eq!(value["zeta"]["a"].as_str(), "{ y: 2, x: 1 }");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_deserialize() {
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Mode {
+        Single,
+        Cluster { nodes: u32 },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Server {
+        host: String,
+        port: u16,
+        timeout: f64,
+        tags: Vec<String>,
+        proxy: Option<String>,
+        mode: Mode,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct AppConfig {
+        name: String,
+        debug: bool,
+        servers: Vec<Server>,
+    }
+
+    let oml_str = r#"
+[app]
+name = "demo"
+debug = false
+
+[[app.servers]]
+host = "127.0.0.1"
+port = 8000 + 80
+timeout = 3
+tags = ["a", "b"]
+proxy = none
+mode = "single"
+
+[[app.servers]]
+host = "10.0.0.1"
+port = 9000
+timeout = 1.5
+tags = []
+proxy = "socks5://proxy"
+mode = { cluster: { nodes: 3 } }
+"#;
+    #[derive(Debug, Deserialize)]
+    struct Root {
+        app: AppConfig,
+    }
+    let root: Root = crate::from_str(oml_str).unwrap();
+    assert_eq!(root.app.name, "demo");
+    assert_eq!(root.app.servers[0].port, 8080);
+    assert_eq!(root.app.servers[0].timeout, 3.0);
+    assert_eq!(root.app.servers[0].proxy, None);
+    assert_eq!(root.app.servers[1].mode, Mode::Cluster { nodes: 3 });
+    assert_eq!(root.app.servers[1].proxy.as_deref(), Some("socks5://proxy"));
+
+    let err = crate::from_str::<Root>(&oml_str.replace("9000", "90000")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "app.servers.1.port: invalid value: integer `90000`, expected u16"
+    );
+    let err = crate::from_str::<Root>(&oml_str.replace("debug = false", "")).unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::Deserialize(("app".to_string(), "missing field `debug`".to_string()))
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serialize() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Backend {
+        Memory,
+        Redis { url: String },
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Cache {
+        size: u32,
+        ratio: f32,
+        backends: Vec<Backend>,
+        owner: Option<String>,
+    }
+
+    let cache = Cache {
+        size: 64,
+        ratio: 0.5,
+        backends: vec![
+            Backend::Memory,
+            Backend::Redis {
+                url: "redis://localhost".to_string(),
+            },
+        ],
+        owner: None,
+    };
+    let value = crate::to_value(&cache).unwrap();
+    assert_eq!(value["size"].as_int(), Some(64));
+    assert_eq!(value["backends"][0].as_str(), "Memory");
+    assert_eq!(
+        value["backends"][1]["Redis"]["url"].as_str(),
+        "redis://localhost"
+    );
+    assert_eq!(crate::from_value::<Cache>(&value).unwrap(), cache);
+    assert_eq!(crate::to_value(&value).unwrap(), value);
+
+    let oml_str = r#"
+[app]
+cache = none
+total = cache.size * 2
+"#;
+    let mut root = OmlExpr::from_str(oml_str).unwrap();
+    root["app"]["cache"].set_value(value);
+    root["app"]["cache"]["size"].set_int(100);
+    let value = root.evalute().unwrap();
+    assert_eq!(value["app"]["total"].as_int(), Some(200));
+    assert_eq!(value["app"]["cache"]["ratio"].as_float(), Some(0.5));
+    let err = crate::to_value(&u64::MAX).unwrap_err();
+    assert_eq!(err.to_string(), "integer 18446744073709551615 out of range");
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_derive_config() {
+    use crate::{IndexMap, OmlConfig};
+
+    /// Database connection
+    #[derive(Debug, OmlConfig)]
+    struct Database {
+        /// Connection string
+        url: String,
+        #[oml(default = 10)]
+        pool: u32,
+    }
+
+    /// Service settings
+    #[derive(Debug, OmlConfig)]
+    struct AppConfig {
+        #[oml(rename = "app")]
+        server: Server,
+        database: Database,
+        #[oml(path = "app.labels")]
+        labels: IndexMap<String, String>,
+    }
+
+    #[derive(Debug, OmlConfig)]
+    struct Server {
+        /// Listen port
+        #[oml(default = 8080)]
+        port: u16,
+        #[oml(default = "0.0.0.0")]
+        host: String,
+        #[oml(default = "C:\\srv\tdata")]
+        dir: String,
+        #[oml(default)]
+        workers: Vec<String>,
+        proxy: Option<String>,
+    }
+
+    let oml_str = r#"
+[app]
+port = 9000 + 1
+labels = { team: "infra" }
+
+[database]
+url = "postgres://db"
+"#;
+    let config = AppConfig::from_oml_str(oml_str).unwrap();
+    assert_eq!(config.server.port, 9001);
+    assert_eq!(config.server.host, "0.0.0.0");
+    assert!(config.server.workers.is_empty());
+    assert_eq!(config.server.proxy, None);
+    assert_eq!(config.database.url, "postgres://db");
+    assert_eq!(config.database.pool, 10);
+    assert_eq!(config.labels["team"], "infra");
+
+    let err = AppConfig::from_oml_str(&oml_str.replace("9000", "90000")).unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::Deserialize((
+            "app.port".to_string(),
+            "expected u16, found 90001".to_string()
+        ))
+    );
+    let err = AppConfig::from_oml_str("[app]\nport = 1").unwrap_err();
+    assert_eq!(err.to_string(), "database.url: missing field");
+
+    let err = Database::oml_template().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "url: root fields must be inside a nested struct"
+    );
+    let template = AppConfig::oml_template().unwrap();
+    let expected = r#"// Service settings
+
+[app]
+labels = {}
+// Listen port
+port = 8080
+host = "0.0.0.0"
+dir = "C:\srv	data"
+workers = []
+proxy = none
+
+// Database connection
+[database]
+// Connection string
+url = ""
+pool = 10
+"#;
+    assert_eq!(template, expected);
+    let config = AppConfig::from_oml_str(&template).unwrap();
+    assert_eq!(config.server.dir, "C:\\srv\tdata");
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_oml_macro() {
+    let mut root = crate::oml! {
+        @template service(port) {
+            addr = $"0.0.0.0:{port:>5}"
+        }
+
+        [hello]
+        value = 12
+        name = $"hello {value + 12}"
+        neg = value - -1
+        ids = [i * 2 for i in range(3)].map(x => x + 1)
+        @let base = 1
+        tier = match value { 1..=10 => "small", _ => "large" }
+        @assert value >= base, "value too small"
+
+        [services.api] = service(port: root.hello.value)
+    };
+    root["hello"]["value"].set_int(30);
+    let value = root.evalute().unwrap();
+    assert_eq!(value["hello"]["name"].as_str(), "hello 42");
+    assert_eq!(value["hello"]["neg"].as_int(), Some(31));
+    assert_eq!(value["hello"]["ids"].as_str(), "[1, 3, 5]");
+    assert_eq!(value["hello"]["tier"].as_str(), "large");
+    assert_eq!(value["services"]["api"]["addr"].as_str(), "0.0.0.0:   30");
+
+    let value = crate::include_oml!("src/test.oml").evalute().unwrap();
+    assert_eq!(value["server"]["addr"].as_str(), "0.0.0.0:8080");
+
+    // the macros run the same checks, so these sources fail to compile
+    let sources = [
+        ("[a] = nosuch(port: 1)", "undefined template: nosuch"),
+        (
+            "@template t(port) {}\n[a] = t(host: 1)",
+            "unknown argument host for template t.",
+        ),
+        (
+            "@template t(port) {}\n[a] = t()",
+            "missing argument port for template t.",
+        ),
+    ];
+    for (src, msg) in sources {
+        assert_eq!(oml_syntax::parse_oml(src).unwrap_err(), msg);
+        let err = OmlExpr::from_str(src).unwrap_err();
+        assert_eq!(err, OmlError::Parse(msg.to_string()));
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json() {
+    let oml_str = r#"
+[app]
+name = "demo"
+port = 8080
+ratio = 0.5
+tags = ["a", "b"]
+proxy = none
+"#;
+    use crate::OmlValue;
+
+    let value = OmlExpr::from_str(oml_str).unwrap().evalute().unwrap();
+    let json = value.to_json_string().unwrap();
+    assert_eq!(
+        json,
+        r#"{"app":{"name":"demo","port":8080,"ratio":0.5,"tags":["a","b"],"proxy":null}}"#
+    );
+    assert!(value
+        .to_json_string_pretty()
+        .unwrap()
+        .starts_with("{\n  \"app\": {\n    \"name\": \"demo\","));
+    assert_eq!(OmlValue::from_json(&json).unwrap(), value);
+    assert!(matches!(
+        OmlValue::from_json("{\"a\": }"),
+        Err(OmlError::Parse(_))
+    ));
+    // like `to_value(&u64::MAX)`, integers beyond i64 are rejected instead of rounded
+    let err = OmlValue::from_json("{\"a\": 18446744073709551615}").unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::Deserialize((
+            "".to_string(),
+            "integer 18446744073709551615 out of range at line 1 column 26".to_string()
+        ))
+    );
+
+    // json provides the defaults, the oml document overrides them
+    let mut root = OmlExpr::from_str(oml_str).unwrap();
+    let json = r#"{"app": {"port": 80, "workers": 4}, "db": {"url": "postgres://db"}}"#;
+    root.apply(OmlExpr::from_json(json).unwrap());
+    root["app"]["workers"].set_int(8);
+    let value = root.evalute().unwrap();
+    assert_eq!(value["app"]["port"].as_int(), Some(8080));
+    assert_eq!(value["app"]["workers"].as_int(), Some(8));
+    assert_eq!(value["db"]["url"].as_str(), "postgres://db");
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_toml() {
+    let toml_str = r#"
+[server]
+host = "localhost"
+port = 8080
+started = 1979-05-27T07:32:00Z
+
+[server.tls]
+enabled = true
+
+[[server.routes]]
+path = "/"
+
+[[server.routes]]
+path = "/api"
+max-body = 1.5
+"#;
+    let (mut expr, warnings) = OmlExpr::from_toml(toml_str).unwrap();
+    assert_eq!(
+        warnings,
+        vec![
+            (
+                "server.started".to_string(),
+                "datetime imported as string".to_string()
+            ),
+            (
+                "server.routes.1.max-body".to_string(),
+                "key \"max-body\" can't be referenced from oml expressions".to_string()
+            ),
+        ]
+    );
+    expr.apply(OmlExpr::from_str("[server]\naddr = $\"{host}:{port}\"").unwrap());
+    let value = expr.evalute().unwrap();
+    assert_eq!(value["server"]["addr"].as_str(), "localhost:8080");
+    assert_eq!(value["server"]["started"].as_str(), "1979-05-27T07:32:00Z");
+    assert_eq!(value["server"]["routes"][1]["path"].as_str(), "/api");
+
+    let toml_out = value.to_toml_string().unwrap();
+    assert!(
+        toml_out.contains("[server.tls]\nenabled = true\n"),
+        "{}",
+        toml_out
+    );
+    assert!(
+        toml_out.contains("[[server.routes]]\npath = \"/\"\n"),
+        "{}",
+        toml_out
+    );
+    let (expr, _) = OmlExpr::from_toml(&toml_out).unwrap();
+    assert_eq!(expr.evalute().unwrap(), value);
+
+    let value = OmlExpr::from_str("[a]\nb = [1, none]")
+        .unwrap()
+        .evalute()
+        .unwrap();
+    let err = value.to_toml_string().unwrap_err();
+    assert_eq!(err.to_string(), "a.b.1: toml arrays can't hold none");
+}
+
+#[test]
+fn test_yaml() {
+    let oml = "[server]
+name = \"no\"
+port = 8080
+version = \"1.10\"
+on = \"on\"
+motd = \"hello
+world
+\"
+banner = \"a: b
+c\"
+ratio = 2.0
+tags = [\"prod\", \"\", \"~\", \"0x1f\", \"- item\"]
+
+[[server.routes]]
+path = \"/\"
+
+[[server.routes]]
+path = \"/api\"
+limits = [1, 2]
+";
+    let value = OmlExpr::from_str(oml).unwrap().evalute().unwrap();
+    let yaml = value.to_yaml_string();
+    assert!(
+        yaml.starts_with("server:\n  name: \"no\"\n  port: 8080\n"),
+        "{}",
+        yaml
+    );
+    assert!(
+        yaml.contains("  motd: |\n    hello\n    world\n"),
+        "{}",
+        yaml
+    );
+    assert!(yaml.contains("  banner: |-\n    a: b\n    c\n"), "{}", yaml);
+    assert!(yaml.contains("  ratio: 2.0\n"), "{}", yaml);
+    assert!(
+        yaml.contains("  routes:\n  - path: /\n  - path: /api\n    limits:\n    - 1\n    - 2\n"),
+        "{}",
+        yaml
+    );
+
+    #[cfg(feature = "serde")]
+    {
+        let parsed: crate::OmlValue = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, value);
+    }
+}
+
+#[test]
+fn test_to_oml_string() {
+    let oml = r#"
+@assert 1 < 2
+[server]
+port = 8080
+@let base = 8000
+@fn twice x = x * 2
+addr = $"{host}:{port:>6}"
+host = "localhost"
+@if port > 8000 mode = "high"
+mode = "low"
+offset = twice(port - base) + (1 - 2) - (3 - 4)
+tls.enabled = false
+tls.cert = none
+
+[server.limits]
+cpu = 2.0
+tags = ["a", "b"]
+doubled = { k: v * 2 for k, v in { x: 1, y: 2 } }
+evens = [n for n in [1, 2, 3, 4] if n % 2 == 0]
+name = let a = 1, b = a + 1 in a + b
+size = match cpu { 0..2 => "small", 2.0 | 3.0 => "medium", _ => "large" }
+extra = { ...doubled, z: 3 }
+loud = tags.map(t => t + "!")
+cond = cpu > 1.0 ? (1 in [1, 2]) : !(-1 < 0)
+
+[[server.routes]]
+path = "/"
+
+[[server.routes]]
+path = "/api"
+meta.auth = true
+"#;
+    let expr = OmlExpr::from_str(oml).unwrap();
+    let out = expr.to_oml_string().unwrap();
+    assert!(
+        out.starts_with("@assert 1 < 2, \"1 < 2\"\n\n[server]\nport = 8080\n@let base = 8000\n"),
+        "{}",
+        out
+    );
+    assert!(out.contains("@fn twice x = x * 2\n"), "{}", out);
+    assert!(out.contains("addr = $\"{host}:{port:>6}\"\n"), "{}", out);
+    assert!(
+        out.contains("@if port > 8000 mode = \"high\"\nmode = \"low\"\n"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("offset = twice(port - base) + (1 - 2) - (3 - 4)\n"),
+        "{}",
+        out
+    );
+    assert!(out.contains("\n[server.tls]\nenabled = false\n"), "{}", out);
+    assert!(
+        out.contains("\n[[server.routes]]\npath = \"/api\"\nmeta.auth = true\n"),
+        "{}",
+        out
+    );
+    let expr2 = OmlExpr::from_str(&out).unwrap();
+    assert_eq!(expr2.to_oml_string().unwrap(), out);
+    assert_eq!(expr2.evalute().unwrap(), expr.evalute().unwrap());
+
+    let value = expr.evalute().unwrap();
+    let out = value.to_oml_string().unwrap();
+    assert_eq!(OmlExpr::from_str(&out).unwrap().evalute().unwrap(), value);
+
+    let expr = OmlExpr::from_str("@if true [a.b]\nx = 1\ny.z = 2").unwrap();
+    assert_eq!(
+        expr.to_oml_string().unwrap(),
+        "@if true [a.b]\nx = 1\ny.z = 2\n"
+    );
+
+    let value = OmlExpr::from_str("[server]\nport = 1")
+        .unwrap()
+        .evalute()
+        .unwrap();
+    let mut map = value.as_map().unwrap();
+    map["server"]["max-conn"] = map["server"]["port"].clone();
+    let err = crate::OmlValue::Map(map).to_oml_string().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "server: key \"max-conn\" isn't a valid oml identifier"
+    );
+    // string literals are raw, only quotes written as `\"` can be represented
+    let group = |text: &str| {
+        let mut map = crate::IndexMap::new();
+        map.insert("s".to_string(), crate::OmlValue::String(text.to_string()));
+        let mut root = crate::IndexMap::new();
+        root.insert("a".to_string(), crate::OmlValue::Map(map));
+        crate::OmlValue::Map(root)
+    };
+    for text in ["a\\\"b", "C:\\dir\\x", "{}", "${HOME}"] {
+        let out = group(text).to_oml_string().unwrap();
+        let value = OmlExpr::from_str(&out).unwrap().evalute().unwrap();
+        assert_eq!(value, group(text), "{}", out);
+    }
+    for text in ["a\"b", "C:\\dir\\"] {
+        let err = group(text).to_oml_string().unwrap_err();
+        assert!(matches!(err, OmlError::Serialize(_)), "{}", err);
+    }
+    let expr = OmlExpr::from_str("[a]\nx = 1\ns = $\"C:\\{x}\\\"{x}\\\"\"").unwrap();
+    let out = expr.to_oml_string().unwrap();
+    let expr2 = OmlExpr::from_str(&out).unwrap();
+    assert_eq!(expr2.evalute().unwrap(), expr.evalute().unwrap(), "{}", out);
+}
+
+#[test]
+fn test_document_edit() {
+    let oml = r#"// deploy settings
+[image]
+name = "app" // keep me
+tag   = "v1.0"   /* bumped by the bot */
+
+[[routes]]
+path = "/"
+
+[[routes]]
+path = "/api"
+"#;
+    let mut doc = crate::OmlDocument::parse(oml).unwrap();
+    assert_eq!(doc.to_string(), oml);
+    let leaves = |node: &crate::OmlCstNode, out: &mut Vec<std::ops::Range<usize>>| {
+        fn walk(node: &crate::OmlCstNode, out: &mut Vec<std::ops::Range<usize>>) {
+            match node.children().is_empty() {
+                true => out.push(node.range()),
+                false => node.children().iter().for_each(|node| walk(node, out)),
+            }
+        }
+        walk(node, out)
+    };
+    let mut ranges = vec![];
+    leaves(doc.root(), &mut ranges);
+    let text: String = ranges.iter().map(|range| &oml[range.clone()]).collect();
+    assert_eq!(text, oml);
+    assert!(ranges.windows(2).all(|w| w[0].end == w[1].start));
+
+    assert_eq!(doc.value_at("image.tag"), Some("\"v1.0\""));
+    assert_eq!(doc.value_at("routes.1.path"), Some("\"/api\""));
+    let range = doc.range_at("image.tag").unwrap();
+    assert_eq!(doc.line_col(range.start), (4, 9));
+    assert_eq!(doc.line_col(doc.range_at("image").unwrap().start), (2, 1));
+    let tag = OmlExpr::from(crate::OmlValue::String("v1.1".to_string()));
+    doc.set_value_at("image.tag", &tag).unwrap();
+    doc.set_value_at(
+        "image.pull",
+        &OmlExpr::from_str("[a]\nb = true").unwrap()["a"]["b"],
+    )
+    .unwrap();
+    doc.set_value_at(
+        "routes.1.path",
+        &OmlExpr::TempName("root.image.name".to_string()),
+    )
+    .unwrap();
+    doc.set_value_at("server.port", &OmlExpr::from(crate::OmlValue::Int64(80)))
+        .unwrap();
+    assert_eq!(
+        doc.to_string(),
+        r#"// deploy settings
+[image]
+name = "app" // keep me
+tag   = "v1.1"   /* bumped by the bot */
+pull = true
+
+[[routes]]
+path = "/"
+
+[[routes]]
+path = root.image.name
+
+[server]
+port = 80
+"#
+    );
+    let value = doc.to_expr().unwrap().evalute().unwrap();
+    assert_eq!(value["routes"][1]["path"].as_str(), "app");
+
+    let err = doc.set_value_at("port", &OmlExpr::None).unwrap_err();
+    assert_eq!(err.to_string(), "port: no group to hold the value");
+
+    let oml = "[deploy]\nimage = { tag: \"v1\" }\n";
+    let mut doc = crate::OmlDocument::parse(oml).unwrap();
+    let err = doc.set_value_at("deploy.image.tag", &tag).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "deploy.image.tag: deploy.image is already assigned a value"
+    );
+    assert_eq!(doc.to_string(), oml);
+}
+
+#[test]
+fn test_format() {
+    let oml = r#"// deploy settings
+
+@assert   1<2
+@template svc(port,host="localhost"){
+   addr=$"{host}:{port}"
+}
+[image]   // the image
+name="app"     // keep me
+tag   = "v1.0"   /* bumped by the bot */
+@let  base=1+2*3
+@if base>3   big=true
+limits=[
+  1,2,
+     3]
+
+
+check = 1<base<10&&(true||false)
+[web]=svc(port:80)
+// the end
+"#;
+    let expected = r#"// deploy settings
+
+@assert 1 < 2
+
+@template svc(port, host = "localhost") {
+    addr = $"{host}:{port}"
+}
+
+[image] // the image
+name = "app" // keep me
+tag = "v1.0" /* bumped by the bot */
+@let base = 1 + 2 * 3
+@if base > 3 big = true
+limits = [
+    1,
+    2,
+    3
+]
+
+check = 1 < base < 10 && (true || false)
+
+[web] = svc(port: 80)
+// the end
+"#;
+    let out = crate::format(oml, crate::FormatOptions::default()).unwrap();
+    assert_eq!(out, expected);
+    assert_eq!(
+        crate::format(&out, crate::FormatOptions::default()).unwrap(),
+        out
+    );
+    assert_eq!(
+        OmlExpr::from_str(&out).unwrap().evalute().unwrap(),
+        OmlExpr::from_str(oml).unwrap().evalute().unwrap()
+    );
+
+    let options = crate::FormatOptions {
+        sort_keys: true,
+        group_spacing: 2,
+        ..Default::default()
+    };
+    let out = crate::format("[b]\ny = 1\n\nx = [1, /* one */ 2]\n[a]\nz=1", options).unwrap();
+    assert_eq!(out, "[b]\nx = [1, /* one */ 2]\ny = 1\n\n\n[a]\nz = 1\n");
+
+    // a line comment after a trailing block comment stays on the statement's line
+    let oml = "[a]\nx = 1 /* block */ // line\ny = 2 /* one */ /* two */\n";
+    let out = crate::format(oml, crate::FormatOptions::default()).unwrap();
+    assert_eq!(out, oml);
+}
+
+#[test]
+fn test_overrides() {
+    let oml_str = r#"
+[hello]
+base = 12
+value = base
+name = $"hello {value}"
+
+[[servers]]
+port = 80
+"#;
+    let mut eroot = OmlExpr::from_str(oml_str).unwrap();
+    eroot
+        .apply_overrides(["hello.value = base * 2 + 6", "hello.tag=\"a=b\""])
+        .unwrap();
+    let vars = [
+        ("APP__SERVERS__0__PORT", "8080"),
+        ("APP__HELLO__EXTRA", "[super.hello.value, name]"),
+        ("OTHER__HELLO__VALUE", "1"),
+    ];
+    let vars = vars.map(|(name, value)| (name.to_string(), value.to_string()));
+    eroot.apply_vars("APP", vars).unwrap();
+    let root = eroot.evalute().unwrap();
+    assert_eq!(root["hello"]["name"].as_str(), "hello 30");
+    assert_eq!(root["hello"]["tag"].as_str(), "a=b");
+    assert_eq!(root["servers"][0]["port"].as_int(), Some(8080));
+    assert_eq!(root["hello"]["extra"][0].as_int(), Some(30));
+    assert_eq!(root["hello"]["extra"][1].as_str(), "hello 30");
+
+    let err = eroot.apply_overrides(["hello.value"]).unwrap_err();
+    assert_eq!(err.to_string(), "invalid override: hello.value");
+    let err = eroot.apply_overrides(["hello.value=1 2"]).unwrap_err();
+    assert!(err.to_string().starts_with("hello.value: "));
+
+    for item in ["a.[x=2", "a..b=2", "a.b-c=2"] {
+        let err = eroot.apply_overrides([item]).unwrap_err();
+        assert!(matches!(err, OmlError::Parse(_)), "{}", err);
+    }
+    let err = eroot.apply_overrides(["servers.3.port=2"]).unwrap_err();
+    assert_eq!(err.to_string(), "servers.3.port: servers has no item 3");
+    let err = eroot.apply_overrides(["hello.base.x=2"]).unwrap_err();
+    assert_eq!(err.to_string(), "hello.base.x: hello.base is not a group");
+    let err = eroot.apply_overrides(["hello.0=2"]).unwrap_err();
+    assert_eq!(err.to_string(), "hello.0: hello is not an array");
+    let root = eroot.evalute().unwrap();
+    assert_eq!(root["servers"].as_array().unwrap().len(), 1);
+    assert_eq!(root["hello"]["base"].as_int(), Some(12));
+
+    // conditional groups keep their condition and the values that aren't overridden
+    for cond in ["true", "false"] {
+        let oml_str = format!("@if {} [a]\nb = 1\nd = 2", cond);
+        let mut eroot = OmlExpr::from_str(&oml_str).unwrap();
+        eroot.apply_overrides(["a.b=3"]).unwrap();
+        let root = eroot.evalute().unwrap();
+        assert_eq!(root["a"]["b"].as_int(), Some(3));
+        assert_eq!(root["a"]["d"].as_int(), (cond == "true").then_some(2));
+    }
+}
+
+#[test]
+fn test_env() {
+    let oml_str = r#"
+[db]
+host = env("DB_HOST", "localhost")
+port = env("DB_PORT", 5432)
+url = "postgres://${DB_USER:-admin}@${DB_HOST}/app"
+secret = env("SECRET_KEY", "none")
+raw = "$${DB_HOST}"
+"#;
+    let eroot = OmlExpr::from_str(oml_str).unwrap();
+    let vars = [
+        ("DB_HOST", "db.local"),
+        ("DB_USER", ""),
+        ("SECRET_KEY", "x"),
+    ];
+    let mut options = crate::EnvOptions {
+        allow: vec!["DB_*".to_string()],
+        vars: Some(vars.map(|(k, v)| (k.to_string(), v.to_string())).into()),
+        ..Default::default()
+    };
+    let root = eroot.evalute_with_env(&options).unwrap();
+    assert_eq!(root["db"]["host"].as_str(), "db.local");
+    assert_eq!(root["db"]["port"].as_int(), Some(5432));
+    assert_eq!(root["db"]["url"].as_str(), "postgres://admin@db.local/app");
+    assert_eq!(root["db"]["secret"].as_str(), "none");
+    assert_eq!(root["db"]["raw"].as_str(), "${DB_HOST}");
+    assert_eq!(
+        OmlExpr::from_str(&eroot.to_oml_string().unwrap())
+            .unwrap()
+            .evalute_with_env(&options)
+            .unwrap(),
+        root
+    );
+
+    // nothing is visible without allowed names, `${...}` is only expanded with options
+    let root = eroot.evalute_with_env(&Default::default()).unwrap();
+    assert_eq!(root["db"]["url"].as_str(), "postgres://admin@/app");
+    let root = eroot.evalute().unwrap();
+    assert_eq!(
+        root["db"]["url"].as_str(),
+        "postgres://${DB_USER:-admin}@${DB_HOST}/app"
+    );
+    assert_eq!(root["db"]["raw"].as_str(), "$${DB_HOST}");
+
+    // printing keeps the literal as written
+    let oml_str = "[a]\nx = \"x ${HOME_X:-d} y\"\nraw = \"$${x}\"\n";
+    let eroot = OmlExpr::from_str(oml_str).unwrap();
+    assert_eq!(eroot.to_oml_string().unwrap(), oml_str);
+    assert_eq!(
+        crate::format(oml_str, crate::FormatOptions::default()).unwrap(),
+        oml_str
+    );
+
+    options.strict = true;
+    options.vars = Some(Default::default());
+    let eroot = OmlExpr::from_str("[db]\nurl = \"${DB_HOST}\"").unwrap();
+    let err = eroot.evalute_with_env(&options).unwrap_err();
+    assert_eq!(
+        err,
+        OmlError::At((
+            "db.url".to_string(),
+            Box::new(OmlError::UndefinedEnv("DB_HOST".to_string()))
+        ))
+    );
+}
+
+#[test]
+fn test_builder() {
+    use crate::{OmlBuilder, OmlSource, OmlValue};
+    let dir = std::env::temp_dir().join(format!("oml_test_builder_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("10-db.oml"), "[db]\npool = 20\nhosts = [\"b\"]").unwrap();
+    std::fs::write(dir.join("20-db.oml"), "[db]\npool = 30\n@assert pool > 0").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not oml").unwrap();
+
+    let defaults = "[db]\npool = 10\nhosts = [\"a\"]\ntimeout = 5\n@assert timeout > 0";
+    let vars = [("APP__DB__POOL", "40"), ("APP__LOG__LEVEL", "\"debug\"")];
+    let builder = OmlBuilder::new()
+        .add_str("defaults", defaults)
+        .unwrap()
+        .add_dir(&dir)
+        .unwrap()
+        .add_env_vars("APP", vars.map(|(k, v)| (k.to_string(), v.to_string())))
+        .unwrap()
+        .add_overrides(["db.pool=50"])
+        .unwrap()
+        .set_value("log.file", OmlValue::String("app.log".to_string()))
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let root = builder.build().evalute().unwrap();
+    assert_eq!(root["db"]["pool"].as_int(), Some(50));
+    assert_eq!(root["db"]["hosts"].as_str(), "[b]");
+    assert_eq!(root["db"]["timeout"].as_int(), Some(5));
+    assert_eq!(root["log"]["level"].as_str(), "debug");
+    assert_eq!(root["log"]["file"].as_str(), "app.log");
+
+    let source = builder.provenance("db.pool").unwrap();
+    assert_eq!(source, &OmlSource::Override("db.pool=50".to_string()));
+    assert_eq!(source.to_string(), "override db.pool=50");
+    let source = builder.provenance("db.hosts.0").unwrap();
+    assert_eq!(source, &OmlSource::File(dir.join("10-db.oml")));
+    let source = builder.provenance("db.timeout").unwrap();
+    assert_eq!(source, &OmlSource::Embedded("defaults".to_string()));
+    let source = builder.provenance("log.level").unwrap();
+    assert_eq!(source, &OmlSource::Env("APP__LOG__LEVEL".to_string()));
+    assert_eq!(builder.provenance("log.file"), Some(&OmlSource::Value));
+    assert_eq!(builder.provenance("db.missing"), None);
+
+    // asserts of every layer are kept
+    let builder = OmlBuilder::new()
+        .add_str("a", "[db]\n@assert false, \"from a\"")
+        .unwrap()
+        .add_str("b", "[db]\n@assert false, \"from b\"")
+        .unwrap();
+    let err = builder.build().evalute().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "assertion failed:\n  db: from b\n  db: from a"
+    );
+
+    // an override keeps the source of the values beside it
+    let builder = OmlBuilder::new()
+        .add_str("a", "[[servers]]\nport = 1\n[[servers]]\nport = 2")
+        .unwrap()
+        .add_overrides(["servers.0.port=9"])
+        .unwrap();
+    let source = builder.provenance("servers.1.port").unwrap();
+    assert_eq!(source, &OmlSource::Embedded("a".to_string()));
+    let source = builder.provenance("servers.0.port").unwrap();
+    assert_eq!(source, &OmlSource::Override("servers.0.port=9".to_string()));
+
+    // a conditional layer only replaces what it sets, and only while its condition holds
+    for cond in [true, false] {
+        let builder = OmlBuilder::new()
+            .add_str("a", "[x]\nv = 1\n[m]\nk = 1\nj = 1")
+            .unwrap()
+            .add_str("b", &format!("@if {} [m]\nk = 2", cond))
+            .unwrap()
+            .add_str("c", "[x]\nw = 3")
+            .unwrap();
+        let root = builder.build().evalute().unwrap();
+        assert_eq!(root["m"]["k"].as_int(), Some(if cond { 2 } else { 1 }));
+        assert_eq!(root["m"]["j"].as_int(), Some(1));
+        assert_eq!(root["x"]["v"].as_int(), Some(1));
+        assert_eq!(root["x"]["w"].as_int(), Some(3));
+        let source = builder.provenance("m.k").unwrap();
+        let name = if cond { "b" } else { "a" };
+        assert_eq!(source, &OmlSource::Embedded(name.to_string()));
+        let source = builder.provenance("m.j").unwrap();
+        assert_eq!(source, &OmlSource::Embedded("a".to_string()));
+
+        let root = (builder.add_overrides(["m.k=4"]).unwrap())
+            .build()
+            .evalute()
+            .unwrap();
+        assert_eq!(root["m"]["k"].as_int(), Some(4));
+        assert_eq!(root["x"]["v"].as_int(), Some(1));
+    }
+}
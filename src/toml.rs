@@ -0,0 +1,105 @@
+use crate::ast::join_path;
+use crate::ast::oml_error::OmlError;
+use crate::{IndexMap, OmlExpr, OmlValue};
+
+fn is_oml_id(key: &str) -> bool {
+    let mut chars = key.chars();
+    let first = chars.next().map(|c| c.is_ascii_alphabetic() || c == '_');
+    first.unwrap_or(false) && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn from_toml_value(
+    value: toml::Value,
+    path: &str,
+    warnings: &mut Vec<(String, String)>,
+) -> OmlExpr {
+    match value {
+        toml::Value::String(s) => OmlExpr::Value(OmlValue::String(s)),
+        toml::Value::Integer(n) => OmlExpr::Value(OmlValue::Int64(n)),
+        toml::Value::Float(f) => OmlExpr::Value(OmlValue::Float64(f)),
+        toml::Value::Boolean(b) => OmlExpr::Value(OmlValue::Bool(b)),
+        toml::Value::Datetime(dt) => {
+            let msg = "datetime imported as string".to_string();
+            warnings.push((path.to_string(), msg));
+            OmlExpr::Value(OmlValue::String(dt.to_string()))
+        }
+        toml::Value::Array(arr) => OmlExpr::Array(
+            (arr.into_iter().enumerate())
+                .map(|(index, item)| from_toml_value(item, &join_path(path, index), warnings))
+                .collect(),
+        ),
+        toml::Value::Table(table) => {
+            let mut map = IndexMap::new();
+            for (key, item) in table.into_iter() {
+                let item_path = join_path(path, &key);
+                if !is_oml_id(&key) {
+                    let msg = format!("key {:?} can't be referenced from oml expressions", key);
+                    warnings.push((item_path.clone(), msg));
+                }
+                map.insert(key, from_toml_value(item, &item_path, warnings));
+            }
+            OmlExpr::Map(map)
+        }
+    }
+}
+
+fn to_toml_value(value: &OmlValue, path: &str) -> Result<Option<toml::Value>, OmlError> {
+    Ok(Some(match value {
+        // toml has no null, none values are left out of tables
+        OmlValue::None => return Ok(None),
+        OmlValue::Bool(b) => toml::Value::Boolean(*b),
+        OmlValue::Int64(n) => toml::Value::Integer(*n),
+        OmlValue::Float64(f) => toml::Value::Float(*f),
+        OmlValue::String(s) => toml::Value::String(s.clone()),
+        OmlValue::Array(arr) => {
+            let mut ret = vec![];
+            for (index, item) in arr.iter().enumerate() {
+                let item_path = join_path(path, index);
+                match to_toml_value(item, &item_path)? {
+                    Some(item) => ret.push(item),
+                    None => {
+                        let msg = format!("{}: toml arrays can't hold none", item_path);
+                        return Err(OmlError::Serialize(msg));
+                    }
+                }
+            }
+            toml::Value::Array(ret)
+        }
+        OmlValue::Map(map) => {
+            let mut ret = toml::Table::new();
+            for (key, item) in map.iter() {
+                if let Some(item) = to_toml_value(item, &join_path(path, key))? {
+                    ret.insert(key.clone(), item);
+                }
+            }
+            toml::Value::Table(ret)
+        }
+    }))
+}
+
+impl OmlValue {
+    /// TOML document of a map value, none values are skipped
+    pub fn to_toml_string(&self) -> Result<String, OmlError> {
+        match to_toml_value(self, "")? {
+            Some(toml::Value::Table(table)) => {
+                toml::to_string(&table).map_err(|err| OmlError::Serialize(err.to_string()))
+            }
+            _ => Err(OmlError::Serialize(
+                "toml document must be a map".to_string(),
+            )),
+        }
+    }
+}
+
+impl OmlExpr {
+    /// Literal expression tree of a TOML document, with `(path, message)` for every construct
+    /// that doesn't map cleanly to oml
+    pub fn from_toml(content: &str) -> Result<(Self, Vec<(String, String)>), OmlError> {
+        let table: toml::Table = content
+            .parse()
+            .map_err(|err: toml::de::Error| OmlError::Parse(err.to_string()))?;
+        let mut warnings = vec![];
+        let expr = from_toml_value(toml::Value::Table(table), "", &mut warnings);
+        Ok((expr, warnings))
+    }
+}
@@ -0,0 +1,192 @@
+use crate::OmlValue;
+
+/// Words YAML 1.1 or 1.2 parsers would read as something other than a string
+const RESERVED_WORDS: &[&str] = &[
+    "y", "Y", "yes", "Yes", "YES", "n", "N", "no", "No", "NO", "true", "True", "TRUE", "false",
+    "False", "FALSE", "on", "On", "ON", "off", "Off", "OFF", "null", "Null", "NULL", "~",
+];
+
+fn needs_quotes(s: &str) -> bool {
+    let first = match s.chars().next() {
+        Some(c) => c,
+        None => return true,
+    };
+    let looks_numeric = first.is_ascii_digit()
+        || (matches!(first, '-' | '+' | '.')
+            && s[1..].starts_with(|c: char| c.is_ascii_digit() || c == '.'))
+        || matches!(s, ".inf" | ".Inf" | ".INF" | ".nan" | ".NaN" | ".NAN");
+    looks_numeric
+        || RESERVED_WORDS.contains(&s)
+        || s.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c) || c.is_whitespace())
+        || s.ends_with(|c: char| c == ':' || c.is_whitespace())
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.chars().any(|c| c.is_control())
+}
+
+fn quote(s: &str) -> String {
+    let mut ret = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\t' => ret.push_str("\\t"),
+            '\r' => ret.push_str("\\r"),
+            c if c.is_control() => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+fn scalar(s: &str) -> String {
+    match needs_quotes(s) {
+        true => quote(s),
+        false => s.to_string(),
+    }
+}
+
+/// Literal block for multi-line strings, `None` when the text can't be written as one
+fn block_scalar(s: &str, indent: usize) -> Option<String> {
+    let body = s.trim_end_matches('\n');
+    let block_safe = !body.is_empty()
+        && body.contains('\n')
+        && !body.starts_with([' ', '\t'])
+        && !s.chars().any(|c| c.is_control() && c != '\n' && c != '\t');
+    if !block_safe {
+        return None;
+    }
+    let chomp = match s.len() - body.len() {
+        0 => "|-",
+        1 => "|",
+        _ => "|+",
+    };
+    let mut ret = chomp.to_string();
+    for line in body.split('\n') {
+        ret.push('\n');
+        if !line.is_empty() {
+            ret.push_str(&" ".repeat(indent));
+            ret.push_str(line);
+        }
+    }
+    for _ in 1..(s.len() - body.len()) {
+        ret.push('\n');
+    }
+    Some(ret)
+}
+
+fn float(f: f64) -> String {
+    match f {
+        f if f.is_nan() => ".nan".to_string(),
+        f if f.is_infinite() && f > 0.0 => ".inf".to_string(),
+        f if f.is_infinite() => "-.inf".to_string(),
+        f => {
+            let s = f.to_string();
+            match s.contains(['.', 'e', 'E']) {
+                true => s,
+                false => format!("{}.0", s),
+            }
+        }
+    }
+}
+
+/// Text after `key:` or `-`, including the leading space or newline
+fn write_node(ret: &mut String, value: &OmlValue, indent: usize, in_array: bool) {
+    match value {
+        OmlValue::Map(map) if !map.is_empty() => {
+            ret.push('\n');
+            write_map(ret, value, indent + 2);
+        }
+        OmlValue::Array(arr) if !arr.is_empty() => {
+            ret.push('\n');
+            // sequences under a key stay at the key's indentation
+            write_array(ret, value, if in_array { indent + 2 } else { indent });
+        }
+        OmlValue::String(s) => {
+            ret.push(' ');
+            match s.contains('\n') {
+                true => match block_scalar(s, indent + 2) {
+                    Some(block) => ret.push_str(&block),
+                    None => ret.push_str(&quote(s)),
+                },
+                false => ret.push_str(&scalar(s)),
+            }
+            ret.push('\n');
+        }
+        value => {
+            ret.push(' ');
+            ret.push_str(&inline_scalar(value));
+            ret.push('\n');
+        }
+    }
+}
+
+fn inline_scalar(value: &OmlValue) -> String {
+    match value {
+        OmlValue::None => "null".to_string(),
+        OmlValue::Bool(b) => b.to_string(),
+        OmlValue::Int64(n) => n.to_string(),
+        OmlValue::Float64(f) => float(*f),
+        OmlValue::String(s) => scalar(s),
+        OmlValue::Array(_) => "[]".to_string(),
+        OmlValue::Map(_) => "{}".to_string(),
+    }
+}
+
+fn write_map(ret: &mut String, value: &OmlValue, indent: usize) {
+    if let OmlValue::Map(map) = value {
+        for (key, item) in map.iter() {
+            ret.push_str(&" ".repeat(indent));
+            ret.push_str(&scalar(key));
+            ret.push(':');
+            write_node(ret, item, indent, false);
+        }
+    }
+}
+
+fn write_array(ret: &mut String, value: &OmlValue, indent: usize) {
+    if let OmlValue::Array(arr) = value {
+        for item in arr.iter() {
+            ret.push_str(&" ".repeat(indent));
+            ret.push('-');
+            match item {
+                // the first entry of a nested collection shares the line with the dash
+                OmlValue::Map(map) if !map.is_empty() => {
+                    let mut nested = "".to_string();
+                    write_map(&mut nested, item, indent + 2);
+                    ret.push(' ');
+                    ret.push_str(&nested[indent + 2..]);
+                }
+                OmlValue::Array(arr) if !arr.is_empty() => {
+                    let mut nested = "".to_string();
+                    write_array(&mut nested, item, indent + 2);
+                    ret.push(' ');
+                    ret.push_str(&nested[indent + 2..]);
+                }
+                item => write_node(ret, item, indent, true),
+            }
+        }
+    }
+}
+
+impl OmlValue {
+    /// YAML document in block style, keeping the key order
+    pub fn to_yaml_string(&self) -> String {
+        let mut ret = "".to_string();
+        match self {
+            OmlValue::Map(map) if !map.is_empty() => write_map(&mut ret, self, 0),
+            OmlValue::Array(arr) if !arr.is_empty() => write_array(&mut ret, self, 0),
+            OmlValue::String(s) if s.contains('\n') => {
+                ret.push_str(&block_scalar(s, 2).unwrap_or_else(|| quote(s)));
+                ret.push('\n');
+            }
+            value => {
+                ret.push_str(&inline_scalar(value));
+                ret.push('\n');
+            }
+        }
+        ret
+    }
+}
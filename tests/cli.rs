@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const CONFIG: &str = "[db]
+host = \"localhost\"
+port = 5432
+url = $\"{host}:{port}\"
+";
+
+fn write_file(name: &str, content: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("oml-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+fn oml(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_oml"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn test_eval_and_get() {
+    let file = write_file("eval.oml", CONFIG);
+    let file = file.to_str().unwrap();
+    let output = oml(&["eval", file, "--path", "db.port"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "5432\n");
+    let output = oml(&["get", "db.url", file]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "localhost:5432\n");
+    let output = oml(&["get", "db.user", file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "db.user: no such key\n");
+}
+
+#[test]
+fn test_check() {
+    let file = write_file("check_ok.oml", CONFIG);
+    assert!(oml(&["check", file.to_str().unwrap()]).status.success());
+    let file = write_file(
+        "check_err.oml",
+        "[a]\nx = 1\ny = x / 0\n\n[b]\nk = 3\n@assert k > 5\n",
+    );
+    let file = file.to_str().unwrap();
+    let output = oml(&["check", file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with(&format!("{}:3:5: division by zero\n", file)));
+    let output = oml(&["eval", file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), format!("{}:3:5: division by zero\n", file));
+    let file = write_file("check_assert.oml", "[b]\nk = 3\n@assert k > 5\n");
+    let file = file.to_str().unwrap();
+    let output = oml(&["check", file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with(&format!("{}:3:1: assertion failed: k > 5\n", file)));
+}
+
+#[test]
+fn test_fmt_check() {
+    let file = write_file("fmt_ok.oml", CONFIG);
+    assert!(oml(&["fmt", "--check", file.to_str().unwrap()])
+        .status
+        .success());
+    let file = write_file("fmt_err.oml", "[db]\nport=5432\n");
+    let file = file.to_str().unwrap();
+    let output = oml(&["fmt", "--check", file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), format!("not formatted: {}\n", file));
+    // --check leaves the file alone
+    assert_eq!(std::fs::read_to_string(file).unwrap(), "[db]\nport=5432\n");
+}
+
+#[test]
+fn test_convert() {
+    let file = write_file("convert.oml", CONFIG);
+    let output = oml(&["convert", file.to_str().unwrap(), "--to", "yaml"]);
+    assert!(output.status.success());
+    let value: serde_yaml::Value = serde_yaml::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["db"]["url"].as_str(), Some("localhost:5432"));
+    assert_eq!(value["db"]["port"].as_i64(), Some(5432));
+    let output = oml(&["convert", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+}